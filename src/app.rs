@@ -8,10 +8,14 @@ use anyhow::Result;
 use crossterm::{
     ExecutableCommand, QueueableCommand,
     cursor::{MoveDown, MoveTo, MoveToColumn, RestorePosition, SavePosition, SetCursorStyle},
-    style::{Color, Print, SetForegroundColor},
+    event::{
+        DisableBracketedPaste, DisableFocusChange, EnableBracketedPaste, EnableFocusChange,
+        KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    style::{Color, Print, SetBackgroundColor, SetForegroundColor},
     terminal::{
         Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode,
-        enable_raw_mode, size,
+        enable_raw_mode, size, supports_keyboard_enhancement,
     },
 };
 use tokio::{
@@ -21,10 +25,17 @@ use tokio::{
 };
 
 use crate::{
-    config::Quote,
+    config::{IdleAction, Length, Quote, Quoter, Settings, WordList},
     error::{TerminalTooSmallError, TyperError, WordTooLongError},
-    input::{Event, handle_input},
-    state::State,
+    ghost::Ghost,
+    history::{
+        Log, Mode, Record, load_records, next_id, now, personal_best, save_log, save_record,
+    },
+    input::{Action, Event, Keybindings, handle_input},
+    layout::Layout,
+    state::{Key, Keystroke, State},
+    stats::{bar_chart, consistency, dwell_time, flight_time, keystrokes_per_second, squeeze, wpm},
+    text::{grapheme_count, graphemes, is_prefix, normalize, same_grapheme},
 };
 
 pub const TICK_RATE: u64 = 1000 / 60;
@@ -32,31 +43,72 @@ pub const MIN_TERM_COL: u16 = 40;
 pub const MIN_TERM_ROW: u16 = 10;
 const MAX_QUOTE_LINE: u16 = 80;
 const MIN_MARGIN: u16 = 4;
+const REFILL_THRESHOLD: usize = 30;
+const CHART_HEIGHT: u16 = 8;
+const CHART_LABEL: u16 = 6;
 
-pub struct App<'a> {
+pub struct App {
     stdout: Stdout,
     pub event_tx: Sender<Event>,
     event_rx: Receiver<Event>,
     running: bool,
-    quote: Vec<&'a str>,
+    quote: Vec<String>,
     state: State,
     should_render: bool,
     start: Option<Instant>,
     completed: bool,
     mistake_count: u32,
     mistakes: HashSet<(usize, usize)>,
-    raw_quote: &'a str,
+    raw_quote: String,
     error: Option<TyperError>,
+    time_limit: Option<Duration>,
+    shown_remaining: u64,
+    quoter: Option<Quoter>,
+    requested_length: Option<Length>,
+    word_list: Option<WordList>,
+    mode: Mode,
+    length: Option<Length>,
+    quote_id: Option<u64>,
+    /// Where each quote taken from the quoter ends in the text, in words,
+    /// along with its position in the collection
+    collection_quotes: Vec<(usize, usize)>,
+    source: Option<String>,
+    personal_best: Option<f64>,
+    keystrokes: Vec<Keystroke>,
+    result: Option<Record>,
+    replaying: Option<u64>,
+    replay_log: Vec<Keystroke>,
+    speed: f64,
+    ghost: Option<Ghost>,
+    ghost_shown: (usize, usize),
+    invalid: Option<&'static str>,
+    paused_at: Option<Instant>,
+    countdown_until: Option<Instant>,
+    shown_countdown: u64,
+    last_input: Instant,
+    /// Paused until the next key press rather than until the terminal gets
+    /// focus back
+    resume_on_key: bool,
+    show_stats: bool,
+    shown_stats: Option<(u64, u64)>,
+    keyboard_enhanced: bool,
+    settings: Settings,
+    mistyped_words: HashSet<usize>,
+    restarted: bool,
+    /// What the keys on the result screen do, following the key bindings
+    results_footer: String,
 }
 
-impl App<'_> {
-    pub fn new(quote: &Quote) -> App<'_> {
+impl App {
+    pub fn new(quote: &Quote, mode: Mode) -> App {
         let (event_tx, event_rx): (Sender<Event>, Receiver<Event>) = channel(10);
-        let quote = quote.text.as_str();
+        let raw_quote = normalize(&quote.text);
+        let words = split_words(&raw_quote);
         App {
             stdout: stdout(),
-            quote: quote.split_whitespace().filter(|s| !s.is_empty()).collect(),
-            raw_quote: quote,
+            collection_quotes: quote.index.map(|i| (words.len(), i)).into_iter().collect(),
+            quote: words,
+            raw_quote,
             event_rx,
             event_tx,
             running: false,
@@ -67,93 +119,429 @@ impl App<'_> {
             mistake_count: 0,
             mistakes: HashSet::new(),
             error: None,
+            time_limit: None,
+            shown_remaining: 0,
+            quoter: None,
+            requested_length: None,
+            word_list: None,
+            mode,
+            length: quote.length,
+            quote_id: quote.id,
+            source: quote.source.clone(),
+            personal_best: None,
+            keystrokes: Vec::new(),
+            result: None,
+            replaying: None,
+            replay_log: Vec::new(),
+            speed: 1.0,
+            ghost: None,
+            ghost_shown: (0, 0),
+            invalid: None,
+            paused_at: None,
+            countdown_until: None,
+            shown_countdown: 0,
+            last_input: Instant::now(),
+            resume_on_key: false,
+            show_stats: false,
+            shown_stats: None,
+            keyboard_enhanced: false,
+            settings: Settings::default(),
+            mistyped_words: HashSet::new(),
+            restarted: false,
+            results_footer: String::new(),
+        }
+    }
+
+    /// Runs against a countdown instead of ending with the quote. When a
+    /// quoter is set, new quotes get appended as the user approaches the end
+    /// of the text.
+    pub fn timed(quote: &Quote, secs: u64) -> App {
+        let mut app = App::new(quote, Mode::Time(secs));
+        app.time_limit = Some(Duration::from_secs(secs));
+        app.shown_remaining = secs;
+        app.length = None;
+        return app;
+    }
+
+    /// Where new quotes of the `length` group come from when moving on to the
+    /// next one, or when running out of text in timed mode.
+    pub fn set_quoter(&mut self, quoter: Quoter, length: Option<Length>) {
+        self.quoter = Some(quoter);
+        self.requested_length = length;
+    }
+
+    /// Where new words come from when moving on to the next test in word
+    /// mode.
+    pub fn set_word_list(&mut self, word_list: WordList) {
+        self.word_list = Some(word_list);
+    }
+
+    /// Plays back the keystrokes of the run with the given ID instead of
+    /// reading input, `speed` times faster than they were typed.
+    pub fn set_replay(&mut self, id: u64, keystrokes: Vec<Keystroke>, speed: f64) {
+        self.replaying = Some(id);
+        self.replay_log = keystrokes;
+        self.speed = speed;
+    }
+
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
+
+    pub fn set_ghost(&mut self, ghost: Ghost) {
+        self.ghost = Some(ghost);
+    }
+
+    /// Time since the start of the run, as seen by the run. Replays make it
+    /// pass faster and it stands still while paused or before the start.
+    fn elapsed(&self) -> Duration {
+        let Some(start) = self.start else {
+            return Duration::ZERO;
+        };
+        let now = self.paused_at.unwrap_or_else(Instant::now);
+        (now - start).mul_f64(self.speed)
+    }
+
+    /// Gets the clock ready for a new run. It starts on the first key press,
+    /// or once the countdown is over if there's one. Replays start right away.
+    fn begin(&mut self) {
+        self.start = None;
+        self.countdown_until = None;
+        if self.replaying.is_some() {
+            self.start = Some(Instant::now());
+        } else if self.settings.countdown > 0 {
+            self.shown_countdown = self.settings.countdown;
+            self.countdown_until =
+                Some(Instant::now() + Duration::from_secs(self.settings.countdown));
+        }
+    }
+
+    fn pause(&mut self) {
+        if self.paused_at.is_none() && self.start.is_some() && self.replaying.is_none() {
+            self.paused_at = Some(Instant::now());
         }
     }
 
-    async fn run(&mut self) -> Result<(f64, f64, String)> {
+    /// Moves the start forward by the time spent paused, so it doesn't count.
+    /// Coming back also counts as activity for the idle timeout.
+    fn resume(&mut self) {
+        self.resume_on_key = false;
+        self.last_input = Instant::now();
+        if let Some(paused_at) = self.paused_at.take()
+            && let Some(start) = self.start
+        {
+            self.start = Some(start + paused_at.elapsed());
+        }
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        let layout = match &self.settings.layout {
+            Some(name) => Layout::load(name)?,
+            None => Layout::default(),
+        };
+        let keybindings = Keybindings::new(&self.settings.keybindings)?;
+        self.results_footer = results_footer(&keybindings);
+        let mut guard = TerminalGuard {
+            keyboard_enhanced: false,
+        };
         self.stdout
             .execute(EnterAlternateScreen)?
+            .execute(EnableBracketedPaste)?
+            .execute(EnableFocusChange)?
             .execute(SetCursorStyle::SteadyBar)?;
         enable_raw_mode()?;
+        // Needed to tell presses, repeats and releases apart. Without
+        // reporting all keys as escape codes letters never send releases or
+        // repeats, and the alternate keys make Shift+1 arrive as '!'.
+        self.keyboard_enhanced = supports_keyboard_enhancement().unwrap_or(false);
+        if self.keyboard_enhanced {
+            self.stdout.execute(PushKeyboardEnhancementFlags(
+                KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                    | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                    | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
+                    | KeyboardEnhancementFlags::REPORT_ALTERNATE_KEYS,
+            ))?;
+            guard.keyboard_enhanced = true;
+        }
 
-        spawn(start_input_handler(self.event_tx.clone()));
+        spawn(start_input_handler(
+            self.event_tx.clone(),
+            layout,
+            keybindings,
+        ));
         spawn(start_tick_generator(self.event_tx.clone()));
 
+        self.refill()?;
         self.running = true;
-        self.start = Some(Instant::now());
+        self.begin();
+        if self.replaying.is_some() {
+            spawn(start_replayer(
+                self.event_tx.clone(),
+                std::mem::take(&mut self.replay_log),
+                self.start.unwrap(),
+                self.speed,
+            ));
+        }
         while self.running {
             self.process().await?;
+            if self.completed && self.result.is_none() {
+                let record = self.build_record()?;
+                self.save(&record)?;
+                self.result = Some(record);
+                self.should_render = true;
+            }
         }
-        let time = self.start.unwrap().elapsed().as_millis();
-        let total_chars = self
-            .quote
-            .iter()
-            .map(|w| w.chars().count() as f64)
-            .sum::<f64>()
-            + self.quote.len() as f64
-            - 1.0;
-        let wpm = total_chars / 5.0 * 60000.0 / time as f64;
-        let accuracy = total_chars * 100.0 / (total_chars + self.mistake_count as f64);
-        let history = self.generate_mistake_locations().await;
-
-        disable_raw_mode()?;
-        self.stdout.execute(LeaveAlternateScreen)?;
-        return Ok((wpm, accuracy, history));
+        drop(guard);
+        return Ok(());
     }
 
     pub async fn start(&mut self) -> Result<()> {
-        let (wpm, accuracy, history) = self.run().await?;
-        if self.completed {
-            println!(
-                "Mistake history:\n{}\n\nYour stats\nWPM: {}\nAccuracy: {}%\nMistakes: {}",
-                history,
-                wpm.round(),
-                accuracy.round(),
-                self.mistake_count
-            );
+        self.load_personal_best()?;
+        self.run().await?;
+        if let Some(error) = &self.error {
+            println!("{}", error);
+        }
+        return Ok(());
+    }
+
+    fn load_personal_best(&mut self) -> Result<()> {
+        if self.replaying.is_none() {
+            self.personal_best = personal_best(&load_records()?, self.mode, self.length);
+        }
+        return Ok(());
+    }
+
+    fn save(&mut self, record: &Record) -> Result<()> {
+        if self.replaying.is_some() || self.invalid.is_some() {
+            return Ok(());
+        }
+        save_record(record)?;
+        save_log(
+            record.id,
+            &Log {
+                text: self.raw_quote.clone(),
+                keystrokes: self.keystrokes.clone(),
+                settings: self.settings.clone(),
+            },
+        )?;
+
+        let Some(quoter) = self.quoter.as_mut() else {
+            return Ok(());
+        };
+        // Only quotes typed to the end count as seen
+        let mut typed_words = self.state.current;
+        if self.state.current < self.quote.len()
+            && grapheme_count(&self.state.buffer) == grapheme_count(&self.quote[self.state.current])
+            && is_prefix(
+                &self.state.buffer,
+                &self.quote[self.state.current],
+                self.settings.ignore_diacritics,
+            )
+        {
+            typed_words += 1;
+        }
+        for (end, index) in &self.collection_quotes {
+            if *end <= typed_words {
+                quoter.mark_typed(*index)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Starts over, on a new text when `next` is set and there's somewhere to
+    /// take it from.
+    fn restart(&mut self, next: bool) -> Result<()> {
+        if self.replaying.is_some() {
+            return Ok(());
+        }
+        let quote = match (next, self.mode) {
+            (false, _) => None,
+            (true, Mode::Words(count)) => match self.word_list.as_mut() {
+                Some(list) => Some(list.get_words(count as usize)?),
+                None => None,
+            },
+            (true, _) => match self.quoter.as_mut() {
+                Some(quoter) => Some(quoter.get(self.requested_length)?),
+                None => None,
+            },
+        };
+        if let Some(quote) = quote {
+            self.raw_quote = normalize(&quote.text);
+            self.quote = split_words(&self.raw_quote);
+            self.quote_id = quote.id;
+            self.collection_quotes = quote
+                .index
+                .map(|i| (self.quote.len(), i))
+                .into_iter()
+                .collect();
+            self.source = quote.source;
+            if self.time_limit.is_none() {
+                self.length = quote.length;
+            }
+            if let Some(Ghost::Replay(_)) = self.ghost {
+                self.ghost = None;
+            }
         }
-        if self.error.is_some() {
-            println!("{}", self.error.as_ref().unwrap());
+        self.load_personal_best()?;
+
+        self.state = State::default();
+        self.completed = false;
+        self.mistake_count = 0;
+        self.mistakes.clear();
+        self.mistyped_words.clear();
+        self.keystrokes.clear();
+        self.result = None;
+        self.invalid = None;
+        self.paused_at = None;
+        self.resume_on_key = false;
+        self.ghost_shown = (0, 0);
+        if let Some(limit) = self.time_limit {
+            self.shown_remaining = limit.as_secs();
         }
+        self.refill()?;
+        self.begin();
+        self.should_render = true;
         return Ok(());
     }
 
-    async fn generate_mistake_locations(&self) -> String {
-        let mut miss: Vec<(usize, usize)> = self.mistakes.iter().copied().collect();
-        miss.sort();
-        miss.reverse();
+    fn build_record(&self) -> Result<Record> {
+        let (time, total_chars) = match self.time_limit {
+            // The text can run out before the time does when there's nowhere
+            // to take more from
+            Some(limit) => (
+                self.elapsed().min(limit).as_millis(),
+                self.correct_chars() as f64,
+            ),
+            // Runs aborted halfway only count what was typed
+            None if self.invalid.is_some() => {
+                (self.elapsed().as_millis(), self.correct_chars() as f64)
+            }
+            None => (
+                self.elapsed().as_millis(),
+                self.quote
+                    .iter()
+                    .map(|w| grapheme_count(w) as f64)
+                    .sum::<f64>()
+                    + self.quote.len() as f64
+                    - 1.0,
+            ),
+        };
+        let duration = Duration::from_millis(time as u64);
+        let accuracy = total_chars * 100.0 / (total_chars + self.mistake_count as f64).max(1.0);
+        // JSON has no infinity or NaN, and records holding them couldn't be
+        // read back
+        let finite = |v: f64| if v.is_finite() { v } else { 0.0 };
+        let id = match self.replaying {
+            Some(id) => id,
+            None => next_id()?,
+        };
+        return Ok(Record {
+            id,
+            timestamp: now(),
+            mode: self.mode,
+            length: self.length,
+            quote_id: self.quote_id,
+            source: self.source.clone(),
+            wpm: finite(wpm(total_chars, duration)),
+            raw_wpm: finite(wpm(
+                self.keystrokes.iter().filter(|k| k.is_press()).count() as f64,
+                duration,
+            )),
+            consistency: finite(consistency(&self.keystrokes, duration)),
+            accuracy: finite(accuracy),
+            mistakes: self.mistake_count,
+            duration_ms: time as u64,
+        });
+    }
 
-        let a: Vec<String> = self
-            .quote
+    /// Characters typed correctly so far, counting the spaces after completed
+    /// words.
+    fn correct_chars(&self) -> usize {
+        let completed = self.quote[..self.state.current]
             .iter()
-            .enumerate()
-            .map(|(i, w)| {
-                let mut ris = String::new();
-                for (j, c) in w.chars().enumerate() {
-                    match miss.last() {
-                        Some((a, b)) if *a == i && *b == j => {
-                            ris.push_str("\x1b[31m");
-                            ris.push(c);
-                            ris.push_str("\x1b[0m");
-                            miss.pop();
-                        }
-                        _ => ris.push(c),
-                    }
-                }
-                ris
-            })
-            .collect();
-        return a.join(" ");
+            .map(|w| grapheme_count(w) + 1)
+            .sum::<usize>();
+        let partial = graphemes(&self.state.buffer)
+            .iter()
+            .zip(graphemes(&self.quote[self.state.current]))
+            .filter(|(t, e)| same_grapheme(t, e, self.settings.ignore_diacritics))
+            .count();
+        return completed + partial;
     }
 
     async fn process(&mut self) -> Result<()> {
-        let event = self.event_rx.recv().await.unwrap();
+        let mut event = self.event_rx.recv().await.unwrap();
+        if !matches!(
+            event,
+            Event::Render | Event::ForceRender | Event::KeyRelease(_)
+        ) {
+            // Tab followed by Enter moves on to the next quote
+            if self.restarted && event == Event::Confirm {
+                event = Event::NextQuote;
+            }
+            self.restarted = event == Event::Restart;
+        }
+        if matches!(
+            event,
+            Event::KeyPress(_) | Event::Backspace | Event::DeleteWord
+        ) {
+            self.last_input = Instant::now();
+        }
+
+        if self.result.is_some() {
+            match event {
+                Event::Terminate | Event::Confirm | Event::KeyPress('q') => self.running = false,
+                Event::Restart => self.restart(false)?,
+                Event::NextQuote => self.restart(true)?,
+                Event::Render => self.render_results().await?,
+                _ => (),
+            }
+            if event != Event::Render {
+                self.should_render = true;
+            }
+            return Ok(());
+        }
+
         match event {
             Event::Terminate => self.running = false,
-            Event::KeyPress(k) => self.handle_keypress(k).await?,
-            Event::Backspace => self.handle_backspace().await,
-            Event::Render => self.render().await?,
-            Event::ForceRender => (),
+            Event::KeyPress(_) | Event::Backspace | Event::DeleteWord
+                if self.replaying.is_some() || self.countdown_until.is_some() => {}
+            Event::KeyPress(k) | Event::Replay(Key::Press(k)) => {
+                if self.start.is_none() {
+                    self.start = Some(Instant::now());
+                }
+                // In case the terminal didn't report the focus coming back
+                self.resume();
+                self.handle_keypress(k).await?
+            }
+            Event::Backspace | Event::Replay(Key::Backspace) => {
+                self.resume();
+                self.handle_backspace().await
+            }
+            Event::DeleteWord | Event::Replay(Key::DeleteWord) => {
+                self.resume();
+                self.handle_delete_word().await
+            }
+            Event::Render => {
+                self.tick();
+                self.render().await?
+            }
+            Event::KeyRelease(k) => self.handle_release(k),
+            Event::Paste => self.invalid = Some("Text was pasted"),
+            Event::Pause => self.pause(),
+            Event::Resume => self.resume(),
+            Event::TogglePause if self.paused_at.is_some() => self.resume(),
+            Event::TogglePause => {
+                self.pause();
+                self.resume_on_key = self.paused_at.is_some();
+            }
+            Event::ToggleStats => {
+                self.show_stats = !self.show_stats;
+                self.shown_stats = self.live_stats();
+            }
+            Event::Restart => self.restart(false)?,
+            Event::NextQuote => self.restart(true)?,
+            Event::ForceRender | Event::Confirm => (),
         }
 
         if event != Event::Render {
@@ -162,43 +550,449 @@ impl App<'_> {
         return Ok(());
     }
 
+    /// Pauses or aborts the run once nothing got typed for longer than the
+    /// idle timeout. The time spent idle doesn't count either way.
+    fn check_idle(&mut self) {
+        let timeout = Duration::from_secs(self.settings.idle_timeout);
+        if timeout.is_zero()
+            || self.start.is_none()
+            || self.paused_at.is_some()
+            || self.replaying.is_some()
+            || self.last_input.elapsed() < timeout
+        {
+            return;
+        }
+        match self.settings.idle_action {
+            IdleAction::Pause => {
+                // The start already moved past earlier pauses, which mustn't
+                // be taken out of the clock twice
+                self.paused_at = Some(self.last_input.max(self.start.unwrap()));
+                self.resume_on_key = true;
+            }
+            IdleAction::Abort => {
+                // The run ends with the last key typed, not once the idle
+                // time is over
+                self.paused_at = Some(self.last_input.max(self.start.unwrap()));
+                self.invalid = Some("You went AFK");
+                self.completed = true;
+            }
+        }
+        self.should_render = true;
+    }
+
+    fn header(&self) -> String {
+        let mut parts = Vec::new();
+        if self.time_limit.is_some() {
+            parts.push(self.shown_remaining.to_string());
+        }
+        if let Some(pb) = self.personal_best {
+            parts.push(format!("PB {}", pb.round()));
+        }
+        if let Some((wpm, accuracy)) = self.shown_stats {
+            parts.push(format!("{} WPM  {}%", wpm, accuracy));
+        }
+        if let Some(reason) = self.invalid {
+            parts.push(format!("{}, this run won't be saved", reason));
+        }
+        return parts.join("  ");
+    }
+
+    /// WPM and accuracy so far, when they're shown.
+    fn live_stats(&self) -> Option<(u64, u64)> {
+        let elapsed = self.elapsed();
+        // WPM over the first few keys would be wildly off
+        if !self.show_stats || elapsed < Duration::from_secs(1) {
+            return None;
+        }
+        let correct = self.correct_chars() as f64;
+        let accuracy = correct * 100.0 / (correct + self.mistake_count as f64).max(1.0);
+        return Some((
+            wpm(correct, elapsed).round() as u64,
+            accuracy.round() as u64,
+        ));
+    }
+
+    fn tick(&mut self) {
+        if let Some(until) = self.countdown_until {
+            let now = Instant::now();
+            if now < until {
+                let left = (until - now).as_secs_f64().ceil() as u64;
+                if left != self.shown_countdown {
+                    self.shown_countdown = left;
+                    self.should_render = true;
+                }
+                return;
+            }
+            self.countdown_until = None;
+            self.start = Some(until);
+            self.last_input = until;
+            self.should_render = true;
+        }
+        self.check_idle();
+        if self.completed {
+            return;
+        }
+        let stats = self.live_stats();
+        if stats != self.shown_stats {
+            self.shown_stats = stats;
+            self.should_render = true;
+        }
+
+        if let Some(ghost) = &self.ghost {
+            let position = ghost.position(&self.quote, self.elapsed());
+            if position != self.ghost_shown {
+                self.ghost_shown = position;
+                self.should_render = true;
+            }
+        }
+
+        let Some(limit) = self.time_limit else {
+            return;
+        };
+        let elapsed = self.elapsed();
+        if elapsed >= limit {
+            self.completed = true;
+            return;
+        }
+        let remaining = (limit - elapsed).as_secs_f64().ceil() as u64;
+        if remaining != self.shown_remaining {
+            self.shown_remaining = remaining;
+            self.should_render = true;
+        }
+    }
+
+    fn refill(&mut self) -> Result<()> {
+        let Some(quoter) = self.quoter.as_mut() else {
+            return Ok(());
+        };
+        if self.time_limit.is_none() || self.quote.len() - self.state.current > REFILL_THRESHOLD {
+            return Ok(());
+        }
+        let quote = quoter.get(self.requested_length)?;
+        let text = normalize(&quote.text);
+        self.quote.extend(split_words(&text));
+        self.raw_quote.push(' ');
+        self.raw_quote.push_str(&text);
+        if let Some(index) = quote.index {
+            self.collection_quotes.push((self.quote.len(), index));
+        }
+        return Ok(());
+    }
+
     async fn handle_keypress(&mut self, k: char) -> Result<()> {
+        let previous_len = self.state.buffer_len;
+        let previous = self.state.buffer.clone();
         self.state.buffer.push(k);
-        self.state.buffer_len += 1;
-        let current_word = self.quote[self.state.current];
-        let last_byte = self.state.buffer.char_indices().last().unwrap().0;
-        let is_word_completed = self.state.buffer[..last_byte] == *current_word;
-        let is_text_completed =
-            self.state.buffer == current_word && self.state.current == self.quote.len() - 1;
-        let is_correct = self.state.buffer_len <= current_word.chars().count()
-            && self.state.buffer.chars().last().unwrap()
-                == current_word.chars().nth(self.state.buffer_len - 1).unwrap();
-
-        if is_word_completed && k == ' ' {
+        self.state.buffer = normalize(&self.state.buffer);
+        self.state.buffer_len = grapheme_count(&self.state.buffer);
+        // A combining mark typed on its own, or the second half of a compose
+        // sequence, changes the last character instead of adding one
+        let combined = previous_len > 0 && self.state.buffer_len == previous_len;
+
+        let ignore_diacritics = self.settings.ignore_diacritics;
+        let current_word = self.quote[self.state.current].as_str();
+        let typed = graphemes(&self.state.buffer);
+        let expected = graphemes(current_word);
+        let is_word_completed = k == ' '
+            && typed.len() == expected.len() + 1
+            && is_prefix(
+                &self.state.buffer,
+                &format!("{} ", current_word),
+                ignore_diacritics,
+            );
+        let is_text_completed = typed.len() == expected.len()
+            && is_prefix(&self.state.buffer, current_word, ignore_diacritics)
+            && self.state.current == self.quote.len() - 1;
+        let position = self.state.buffer_len - 1;
+        let is_correct = position < expected.len()
+            && same_grapheme(typed[position], expected[position], ignore_diacritics);
+        // Whether the character the mark got combined with was right
+        let was_correct = combined
+            && position < expected.len()
+            && same_grapheme(
+                graphemes(&previous)[position],
+                expected[position],
+                ignore_diacritics,
+            );
+        self.keystrokes.push(Keystroke {
+            at: self.elapsed(),
+            key: Key::Press(k),
+            expected: self.expected_grapheme(position),
+            correct: is_correct || is_word_completed,
+            held: None,
+        });
+
+        if combined {
+            // The mark fixed the character, so it wasn't a mistake after all
+            if is_correct && !was_correct && self.mistakes.remove(&(self.state.current, position)) {
+                self.mistake_count -= 1;
+            }
+            // Or it spoiled a character that was right
+            if !is_correct && was_correct {
+                self.mistake_count += 1;
+                self.mistyped_words.insert(self.state.current);
+                self.mistakes.insert((self.state.current, position));
+            }
+            if is_text_completed {
+                self.completed = true;
+            }
+        } else if is_word_completed {
             self.state.buffer.clear();
             self.state.buffer_len = 0;
             self.state.current += 1;
+            self.refill()?;
         } else if is_text_completed {
-            self.running = false;
             self.completed = true;
         } else if !is_correct {
             self.mistake_count += 1;
-            if self.state.buffer_len <= current_word.chars().count() {
-                self.mistakes
-                    .insert((self.state.current, self.state.buffer_len - 1));
+            self.mistyped_words.insert(self.state.current);
+            if position < expected.len() {
+                self.mistakes.insert((self.state.current, position));
             }
         }
 
         return Ok(());
     }
 
+    /// The character expected at `position` of the current word, the space
+    /// following it included.
+    fn expected_grapheme(&self, position: usize) -> Option<String> {
+        let current_word = graphemes(&self.quote[self.state.current]);
+        if position == current_word.len() && self.state.current < self.quote.len() - 1 {
+            return Some(String::from(" "));
+        }
+        return current_word.get(position).map(|g| g.to_string());
+    }
+
+    /// Moves back to the end of the previous word when the buffer is empty,
+    /// the settings allow it and mistakes were made while typing that word.
+    fn step_back(&mut self) -> bool {
+        let previous = match self.state.current.checked_sub(1) {
+            Some(p) if self.state.buffer_len == 0 && self.settings.backspace_into_mistakes => p,
+            _ => return false,
+        };
+        if !self.mistyped_words.contains(&previous) {
+            return false;
+        }
+        self.state.current = previous;
+        self.state.buffer = self.quote[previous].clone();
+        self.state.buffer_len = grapheme_count(&self.quote[previous]);
+        return true;
+    }
+
     async fn handle_backspace(&mut self) {
-        if self.state.buffer_len > 0 {
-            self.state.buffer.pop();
+        let position = self.state.buffer_len.saturating_sub(1);
+        let expected = self.expected_grapheme(position);
+        let deleted = graphemes(&self.state.buffer).last().map(|g| g.to_string());
+        let correct = match (&deleted, &expected) {
+            (Some(d), Some(e)) => same_grapheme(d, e, self.settings.ignore_diacritics),
+            _ => false,
+        };
+        self.keystrokes.push(Keystroke {
+            at: self.elapsed(),
+            key: Key::Backspace,
+            expected,
+            correct,
+            held: None,
+        });
+        if self.step_back() {
+            return;
+        }
+        if let Some(deleted) = deleted {
+            let len = self.state.buffer.len() - deleted.len();
+            self.state.buffer.truncate(len);
             self.state.buffer_len -= 1;
         }
     }
 
+    /// Records how long the last press of `k` was held down. Shift might be
+    /// let go first, so the case doesn't have to match.
+    fn handle_release(&mut self, k: char) {
+        let at = self.elapsed();
+        let press = self.keystrokes.iter_mut().rev().find(|ks| {
+            ks.held.is_none()
+                && matches!(ks.key, Key::Press(p) if p.to_lowercase().eq(k.to_lowercase()))
+        });
+        if let Some(press) = press {
+            press.held = Some(at.saturating_sub(press.at));
+        }
+    }
+
+    async fn handle_delete_word(&mut self) {
+        self.keystrokes.push(Keystroke {
+            at: self.elapsed(),
+            key: Key::DeleteWord,
+            expected: None,
+            correct: is_prefix(
+                &self.state.buffer,
+                &self.quote[self.state.current],
+                self.settings.ignore_diacritics,
+            ),
+            held: None,
+        });
+        self.step_back();
+        self.state.buffer.clear();
+        self.state.buffer_len = 0;
+    }
+
+    /// Who the quote comes from, cut to `width` characters. Timed runs go
+    /// through several quotes, so they don't have a single one.
+    fn attribution(&self, width: u16) -> Option<String> {
+        let source = self.source.as_ref()?;
+        if self.time_limit.is_some() {
+            return None;
+        }
+        let attribution = format!("— {}", source);
+        if attribution.chars().count() <= width as usize {
+            return Some(attribution);
+        }
+        let mut cut: String = attribution
+            .chars()
+            .take((width as usize).saturating_sub(1))
+            .collect();
+        cut.push('…');
+        return Some(cut);
+    }
+
+    async fn render_results(&mut self) -> Result<()> {
+        if !self.should_render {
+            return Ok(());
+        }
+        let Some(record) = &self.result else {
+            return Ok(());
+        };
+
+        let (cols, rows) = size()?;
+        if cols < MIN_TERM_COL || rows < MIN_TERM_ROW {
+            self.error = Some(TyperError::TerminalTooSmallError(TerminalTooSmallError));
+            self.running = false;
+            return Ok(());
+        }
+        let width = (cols - MIN_MARGIN * 2 - CHART_LABEL) as usize;
+        let height = CHART_HEIGHT.min(rows - 7);
+        let duration = Duration::from_millis(record.duration_ms);
+        let presses = self.keystrokes.iter().filter(|k| k.is_press());
+        let speeds: Vec<f64> = keystrokes_per_second(presses.clone(), duration)
+            .iter()
+            .map(|c| c * 12.0)
+            .collect();
+        let errors = keystrokes_per_second(presses.filter(|k| !k.correct), duration);
+        let speeds = squeeze(&speeds, width);
+        let errors = squeeze(&errors, width);
+        let max = speeds.iter().copied().fold(1.0, f64::max).ceil();
+
+        let mut row = 1;
+        self.stdout
+            .queue(SetCursorStyle::SteadyBar)?
+            .queue(Clear(ClearType::All))?
+            .queue(MoveTo(MIN_MARGIN, row))?
+            .queue(SetForegroundColor(Color::Yellow))?
+            .queue(Print(format!(
+                "WPM {}  Raw {}  Consistency {}%  Accuracy {}%  Mistakes {}",
+                record.wpm.round(),
+                record.raw_wpm.round(),
+                record.consistency.round(),
+                record.accuracy.round(),
+                record.mistakes
+            )))?;
+        if let (Some(dwell), Some(flight)) =
+            (dwell_time(&self.keystrokes), flight_time(&self.keystrokes))
+        {
+            self.stdout.queue(Print(format!(
+                "  Dwell {}ms  Flight {}ms",
+                dwell.round(),
+                flight.round()
+            )))?;
+        }
+        row += 1;
+        let notice = match (self.replaying, self.invalid, self.personal_best) {
+            (Some(id), _, _) => format!("Replay of run {}", id),
+            (None, Some(reason), _) => format!("{}, this run won't be saved", reason),
+            (None, None, Some(pb)) if record.wpm > pb => format!(
+                "Run {}. New personal best! Previous was {} WPM",
+                record.id,
+                pb.round()
+            ),
+            (None, None, None) => format!("Run {}. New personal best!", record.id),
+            (None, None, _) => format!("Run {}", record.id),
+        };
+        self.stdout
+            .queue(MoveTo(MIN_MARGIN, row))?
+            .queue(SetForegroundColor(Color::Green))?
+            .queue(Print(notice))?;
+
+        // WPM per second
+        row += 2;
+        for (i, line) in bar_chart(&speeds, max, height as usize).iter().enumerate() {
+            let label = match i {
+                0 => max.to_string(),
+                _ if i == height as usize - 1 => String::from("0"),
+                _ => String::new(),
+            };
+            self.stdout
+                .queue(MoveTo(MIN_MARGIN, row))?
+                .queue(SetForegroundColor(Color::Reset))?
+                .queue(Print(format!("{:>5} ", label)))?
+                .queue(SetForegroundColor(Color::Green))?
+                .queue(Print(line))?;
+            row += 1;
+        }
+
+        // Seconds with errors
+        let marks: String = errors
+            .iter()
+            .map(|e| if *e > 0.0 { 'x' } else { ' ' })
+            .collect();
+        self.stdout
+            .queue(MoveTo(MIN_MARGIN, row))?
+            .queue(Print(format!("{:>5} ", "err")))?
+            .queue(SetForegroundColor(Color::Red))?
+            .queue(Print(marks))?;
+        row += 2;
+
+        // Mistake history
+        let typed = self.quote[..=self.state.current.min(self.quote.len() - 1)].join(" ");
+        let lines = format_quote(&typed, cols).await.unwrap_or_default();
+        let mut index = 0;
+        for line in lines {
+            if row >= rows - 2 {
+                break;
+            }
+            self.stdout.queue(MoveTo(MIN_MARGIN, row))?;
+            for word in line {
+                for (j, c) in graphemes(word).into_iter().enumerate() {
+                    let color = match self.mistakes.contains(&(index, j)) {
+                        true => Color::Red,
+                        false => Color::Reset,
+                    };
+                    self.stdout
+                        .queue(SetForegroundColor(color))?
+                        .queue(Print(c))?;
+                }
+                self.stdout.queue(Print(' '))?;
+                index += 1;
+            }
+            row += 1;
+        }
+        if let Some(attribution) = self.attribution(cols.saturating_sub(MIN_MARGIN * 2))
+            && row < rows - 2
+        {
+            self.stdout
+                .queue(MoveTo(MIN_MARGIN, row))?
+                .queue(SetForegroundColor(Color::DarkGrey))?
+                .queue(Print(attribution))?;
+        }
+
+        self.stdout
+            .queue(MoveTo(MIN_MARGIN, rows - 1))?
+            .queue(SetForegroundColor(Color::Reset))?
+            .queue(Print(&self.results_footer))?
+            .queue(SetCursorStyle::SteadyBlock)?;
+        self.stdout.flush()?;
+        self.should_render = false;
+        return Ok(());
+    }
+
     async fn render(&mut self) -> Result<()> {
         if !self.should_render {
             return Ok(());
@@ -210,7 +1004,7 @@ impl App<'_> {
             self.running = false;
             return Ok(());
         }
-        let lines = match format_quote(self.raw_quote, cols).await {
+        let lines = match format_quote(&self.raw_quote, cols).await {
             Ok(v) => v,
             Err(e) => {
                 self.error = Some(TyperError::WordTooLongError(e));
@@ -240,11 +1034,17 @@ impl App<'_> {
             .unwrap()
             .0;
 
+        let top = (rows - 3) / 2 - 2;
+        self.stdout.queue(Clear(ClearType::All))?;
+
+        // Header
+        let header = self.header();
         self.stdout
-            .queue(Clear(ClearType::All))
-            .unwrap()
-            .queue(MoveTo(margin, (rows - 3) / 2 - 2))
-            .unwrap();
+            .queue(MoveTo(margin, top - 1))?
+            .queue(SetForegroundColor(Color::Yellow))?
+            .queue(Print(header))?;
+
+        self.stdout.queue(MoveTo(margin, top))?;
 
         // Prev line
         if current_line > 0 {
@@ -260,11 +1060,11 @@ impl App<'_> {
             .iter()
             .map(|line| line.len())
             .sum::<usize>();
-        for i in 0..lines[current_line].len() {
+        for (i, word) in lines[current_line].iter().enumerate() {
             if i + offset < self.state.current {
                 self.stdout
                     .queue(SetForegroundColor(Color::Green))?
-                    .queue(Print(lines[current_line][i]))?
+                    .queue(Print(word))?
                     .queue(Print(' '))?;
                 continue;
             }
@@ -272,19 +1072,19 @@ impl App<'_> {
             if i + offset > self.state.current {
                 self.stdout
                     .queue(SetForegroundColor(Color::Reset))?
-                    .queue(Print(lines[current_line][i]))?
+                    .queue(Print(word))?
                     .queue(Print(' '))?;
                 continue;
             }
 
-            let cc: Vec<char> = lines[current_line][i].chars().collect();
-            let vv: Vec<char> = self.state.buffer.chars().collect();
-            for j in 0..lines[current_line][i].chars().count() {
-                if self.state.buffer.chars().count() <= j {
+            let cc = graphemes(word);
+            let vv = graphemes(&self.state.buffer);
+            for j in 0..cc.len() {
+                if vv.len() <= j {
                     break;
                 }
 
-                if cc[j] == vv[j] {
+                if same_grapheme(vv[j], cc[j], self.settings.ignore_diacritics) {
                     self.stdout.queue(SetForegroundColor(Color::Green))?;
                 } else {
                     self.stdout.queue(SetForegroundColor(Color::Red))?;
@@ -297,7 +1097,7 @@ impl App<'_> {
                 (ccc, vvv) if ccc < vvv => {
                     self.stdout.queue(SetForegroundColor(Color::Yellow))?;
                     let remaining = vv.iter().skip(cc.len()).fold(String::new(), |mut a, b| {
-                        a.push(*b);
+                        a.push_str(b);
                         a
                     });
                     self.stdout.queue(Print(remaining))?.queue(SavePosition)?;
@@ -305,7 +1105,7 @@ impl App<'_> {
                 (ccc, vvv) if ccc > vvv => {
                     self.stdout.queue(SetForegroundColor(Color::Reset))?;
                     let remaining = cc.iter().skip(vv.len()).fold(String::new(), |mut a, b| {
-                        a.push(*b);
+                        a.push_str(b);
                         a
                     });
                     self.stdout.queue(Print(remaining))?;
@@ -319,12 +1119,12 @@ impl App<'_> {
             .queue(MoveToColumn(margin))?;
 
         // Next line
+        let last_rendered = if current_line == 0 && lines.len() > 2 {
+            2
+        } else {
+            1
+        };
         if lines.len() > 1 && current_line < lines.len() - 1 {
-            let last_rendered = if current_line == 0 && lines.len() > 2 {
-                2
-            } else {
-                1
-            };
             for line in &lines[current_line + 1..current_line + 1 + last_rendered] {
                 self.stdout
                     .queue(SetForegroundColor(Color::Reset))?
@@ -333,6 +1133,78 @@ impl App<'_> {
                     .queue(MoveToColumn(margin))?;
             }
         }
+
+        // Source
+        if let Some(attribution) = self.attribution(cols.saturating_sub(margin + MIN_MARGIN)) {
+            self.stdout
+                .queue(MoveTo(margin, top + 3))?
+                .queue(SetForegroundColor(Color::DarkGrey))?
+                .queue(Print(attribution))?;
+        }
+
+        // Ghost
+        if self.ghost.is_some() {
+            let (word, position) = self.ghost_shown;
+            let first = current_line.saturating_sub(1);
+            let mut start = 0;
+            for (i, line) in lines.iter().enumerate() {
+                if start + line.len() <= word {
+                    start += line.len();
+                    continue;
+                }
+                if i < first || i > current_line + last_rendered {
+                    break;
+                }
+                let len = grapheme_count(&self.quote[word]);
+                let mut column = lines[i][..word - start]
+                    .iter()
+                    .map(|w| grapheme_count(w) + 1)
+                    .sum::<usize>()
+                    + position.min(len);
+                if i == current_line && word > self.state.current {
+                    column += self
+                        .state
+                        .buffer_len
+                        .saturating_sub(grapheme_count(&self.quote[self.state.current]));
+                }
+                let c = graphemes(&self.quote[word])
+                    .get(position)
+                    .map(|g| g.to_string())
+                    .unwrap_or(String::from(" "));
+                self.stdout
+                    .queue(MoveTo(margin + column as u16, top + (i - first) as u16))?
+                    .queue(SetForegroundColor(Color::Black))?
+                    .queue(SetBackgroundColor(Color::Magenta))?
+                    .queue(Print(c))?
+                    .queue(SetBackgroundColor(Color::Reset))?;
+                break;
+            }
+        }
+
+        // Pause overlay
+        if self.paused_at.is_some() {
+            let text = match self.resume_on_key {
+                true => "paused – press a key to resume",
+                false => "paused – click back to resume",
+            };
+            self.stdout
+                .queue(MoveTo((cols - text.chars().count() as u16) / 2, top + 5))?
+                .queue(SetForegroundColor(Color::Black))?
+                .queue(SetBackgroundColor(Color::Yellow))?
+                .queue(Print(text))?
+                .queue(SetBackgroundColor(Color::Reset))?;
+        }
+
+        // Countdown overlay
+        if self.countdown_until.is_some() {
+            let text = format!(" {} ", self.shown_countdown);
+            self.stdout
+                .queue(MoveTo((cols - text.chars().count() as u16) / 2, top + 5))?
+                .queue(SetForegroundColor(Color::Black))?
+                .queue(SetBackgroundColor(Color::Yellow))?
+                .queue(Print(text))?
+                .queue(SetBackgroundColor(Color::Reset))?;
+        }
         self.stdout.queue(RestorePosition)?;
 
         self.stdout.flush()?;
@@ -341,6 +1213,26 @@ impl App<'_> {
     }
 }
 
+/// Puts the terminal back the way it was when the run ends, even if it ends
+/// with an error.
+struct TerminalGuard {
+    keyboard_enhanced: bool,
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut stdout = stdout();
+        if self.keyboard_enhanced {
+            let _ = stdout.execute(PopKeyboardEnhancementFlags);
+        }
+        let _ = disable_raw_mode();
+        let _ = stdout
+            .execute(DisableBracketedPaste)
+            .and_then(|s| s.execute(DisableFocusChange))
+            .and_then(|s| s.execute(LeaveAlternateScreen));
+    }
+}
+
 async fn start_tick_generator(ev: Sender<Event>) {
     while !ev.is_closed() {
         ev.send(Event::Render).await.unwrap();
@@ -348,12 +1240,39 @@ async fn start_tick_generator(ev: Sender<Event>) {
     }
 }
 
-async fn start_input_handler(ev: Sender<Event>) {
+async fn start_replayer(ev: Sender<Event>, keystrokes: Vec<Keystroke>, start: Instant, speed: f64) {
+    for k in keystrokes {
+        tokio::time::sleep_until(start + k.at.div_f64(speed)).await;
+        if ev.send(Event::Replay(k.key)).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn results_footer(keybindings: &Keybindings) -> String {
+    let mut footer = String::from("Enter or q to exit");
+    if let Some(keys) = keybindings.describe(Action::Restart) {
+        footer.push_str(&format!(", {} to restart", keys));
+    }
+    if let Some(keys) = keybindings.describe(Action::NextQuote) {
+        footer.push_str(&format!(", {} for the next one", keys));
+    }
+    return footer;
+}
+
+async fn start_input_handler(ev: Sender<Event>, layout: Layout, keybindings: Keybindings) {
     while !ev.is_closed() {
-        handle_input(&ev).await.unwrap();
+        handle_input(&ev, &layout, &keybindings).await.unwrap();
     }
 }
 
+fn split_words(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
 async fn format_quote(quote: &str, row_len: u16) -> Result<Vec<Vec<&str>>, WordTooLongError> {
     let max = if row_len - (MIN_MARGIN * 2) < MAX_QUOTE_LINE {
         row_len - (MIN_MARGIN * 2)
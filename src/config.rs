@@ -1,59 +1,244 @@
-use std::{env, fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Result, anyhow};
 use rand::{RngExt, rand_core::UnwrapErr, rngs::SysRng};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+use crate::input::Action;
+
+type Group = (usize, usize);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Length {
+    Short,
+    Medium,
+    Long,
+    Huge,
+}
+
+impl Display for Length {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Length::Short => f.write_str("short"),
+            Length::Medium => f.write_str("medium"),
+            Length::Long => f.write_str("long"),
+            Length::Huge => f.write_str("huge"),
+        }
+    }
+}
+
+/// Length ranges, in characters, Monkeytype uses for its quote groups.
+const DEFAULT_GROUPS: (Group, Group, Group, Group) =
+    ((0, 100), (101, 300), (301, 600), (601, 9999));
+
+const DEFAULT_COLLECTION: &str = "english";
+/// How many of the last quotes typed from a collection are skipped when
+/// picking a new one.
+const RECENT_QUOTES: usize = 20;
+
+/// A quote collection in the format of Monkeytype's quote files.
 #[derive(Deserialize)]
 pub struct Quoter {
     #[serde(skip)]
     randomizer: UnwrapErr<SysRng>,
-    groups: (
-        (usize, usize),
-        (usize, usize),
-        (usize, usize),
-        (usize, usize),
-    ),
+    #[serde(skip)]
+    lowercase: bool,
+    #[serde(skip)]
+    unseen_first: bool,
+    /// Name of the collection, keying its entry in recent.json
+    #[serde(skip)]
+    name: String,
+    /// Quotes typed from this collection
+    #[serde(skip)]
+    seen: Seen,
+    #[serde(default)]
+    language: Option<String>,
+    /// Text length ranges of the short, medium, long and huge quotes
+    #[serde(default = "default_groups")]
+    groups: (Group, Group, Group, Group),
     quotes: Vec<Quote>,
 }
 
+fn default_groups() -> (Group, Group, Group, Group) {
+    DEFAULT_GROUPS
+}
+
+/// The entry of a collection in recent.json. Quotes are keyed by their ID, or
+/// by `#` followed by their position in the collection when they have none.
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct Seen {
+    /// The last quotes typed, oldest first
+    recent: Vec<String>,
+    /// How many times each quote was typed
+    counts: HashMap<String, usize>,
+}
+
 impl Quoter {
-    pub fn get_random(&mut self) -> Result<Quote> {
-        self.get_range((0, self.quotes.len()))
+    /// Makes every quote returned from now on lowercase.
+    pub fn set_lowercase(&mut self, lowercase: bool) {
+        self.lowercase = lowercase;
+    }
+
+    /// Picks quotes that were never typed, or typed the fewest times, first.
+    pub fn set_unseen_first(&mut self, unseen_first: bool) {
+        self.unseen_first = unseen_first;
     }
 
-    fn get_range(&mut self, range: (usize, usize)) -> Result<Quote> {
+    /// Picks a quote from the given length group, or from any when there's
+    /// none. The ones typed recently are skipped, unless they're all that's
+    /// left.
+    pub fn get(&mut self, length: Option<Length>) -> Result<Quote> {
         if self.quotes.is_empty() {
             return Err(anyhow!("There are no quotes in your quote files"));
         }
-        let (l, r) = range;
-        if l > r || l > self.quotes.len() || r > self.quotes.len() {
-            return Err(anyhow!("Your quotes file is corrupted"));
+        let mut candidates: Vec<usize> = (0..self.quotes.len())
+            .filter(|i| length.is_none() || self.length_of(&self.quotes[*i]) == length)
+            .collect();
+        if candidates.is_empty() {
+            return Err(anyhow!(
+                "There are no {} quotes in your quote files",
+                length.unwrap()
+            ));
         }
-        Ok(self
-            .quotes
-            .get(
-                self.randomizer
-                    .sample(rand::distr::uniform::Uniform::new(l, r + 1)?),
-            )
-            .cloned()
-            .unwrap())
+
+        let fresh: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|i| !self.seen.recent.contains(&self.key(*i)))
+            .collect();
+        if !fresh.is_empty() {
+            candidates = fresh;
+        }
+        if self.unseen_first {
+            let times = |i: &usize| self.seen.counts.get(&self.key(*i)).copied().unwrap_or(0);
+            let least = candidates.iter().map(times).min().unwrap_or(0);
+            candidates.retain(|i| times(i) == least);
+        }
+
+        let index = candidates[self.randomizer.random_range(0..candidates.len())];
+        Ok(self.prepare(index))
     }
 
-    pub fn get_short(&mut self) -> Result<Quote> {
-        self.get_range(self.groups.0)
+    /// Remembers that the quote at `index` was typed to the end, so it's
+    /// skipped for a while and counted by unseen first.
+    pub fn mark_typed(&mut self, index: usize) -> Result<()> {
+        let key = self.key(index);
+        *self.seen.counts.entry(key.clone()).or_default() += 1;
+        self.seen.recent.retain(|k| *k != key);
+        self.seen.recent.push(key);
+        let excess = self.seen.recent.len().saturating_sub(RECENT_QUOTES);
+        self.seen.recent.drain(..excess);
+        save_seen(&self.name, &self.seen)
     }
 
-    pub fn get_medium(&mut self) -> Result<Quote> {
-        self.get_range(self.groups.1)
+    fn key(&self, index: usize) -> String {
+        match self.quotes[index].id {
+            Some(id) => id.to_string(),
+            None => format!("#{}", index),
+        }
     }
 
-    pub fn get_long(&mut self) -> Result<Quote> {
-        self.get_range(self.groups.2)
+    pub fn get_by_id(&self, id: u64) -> Result<Quote> {
+        self.quotes
+            .iter()
+            .position(|q| q.id == Some(id))
+            .map(|i| self.prepare(i))
+            .ok_or(anyhow!("There's no quote with ID {}", id))
     }
 
-    pub fn get_huge(&mut self) -> Result<Quote> {
-        self.get_range(self.groups.3)
+    /// Quotes whose text or source contain `term`, ignoring case.
+    pub fn search(&self, term: &str) -> Vec<Quote> {
+        let term = term.to_lowercase();
+        (0..self.quotes.len())
+            .filter(|i| {
+                let q = &self.quotes[*i];
+                q.text.to_lowercase().contains(&term)
+                    || q.source
+                        .as_ref()
+                        .is_some_and(|s| s.to_lowercase().contains(&term))
+            })
+            .map(|i| self.prepare(i))
+            .collect()
+    }
+
+    /// Copies the quote at `index` out of the collection, filling in its
+    /// length group and position.
+    fn prepare(&self, index: usize) -> Quote {
+        let mut quote = self.quotes[index].clone();
+        quote.length = self.length_of(&quote);
+        quote.index = Some(index);
+        if self.lowercase {
+            quote.text = quote.text.to_lowercase();
+        }
+        quote
+    }
+
+    /// Finds the length group `quote` belongs to. Quotes longer than the huge
+    /// ones still count as huge.
+    fn length_of(&self, quote: &Quote) -> Option<Length> {
+        let len = quote.text.chars().count();
+        let (short, medium, long, huge) = self.groups;
+        if len > huge.1 {
+            return Some(Length::Huge);
+        }
+        [
+            (short, Length::Short),
+            (medium, Length::Medium),
+            (long, Length::Long),
+            (huge, Length::Huge),
+        ]
+        .into_iter()
+        .find(|((l, r), _)| *l <= len && len <= *r)
+        .map(|(_, length)| length)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WordList {
+    #[serde(skip)]
+    randomizer: UnwrapErr<SysRng>,
+    #[serde(skip)]
+    lowercase: bool,
+    words: Vec<String>,
+}
+
+impl WordList {
+    /// Makes every text returned from now on lowercase.
+    pub fn set_lowercase(&mut self, lowercase: bool) {
+        self.lowercase = lowercase;
+    }
+
+    /// Builds a quote out of `count` words picked at random from the list.
+    pub fn get_words(&mut self, count: usize) -> Result<Quote> {
+        if self.words.is_empty() {
+            return Err(anyhow!("There are no words in your word list"));
+        }
+        let text = (0..count)
+            .map(|_| {
+                self.words[self.randomizer.random_range(0..self.words.len())]
+                    .trim()
+                    .to_string()
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+        Ok(Quote {
+            text: if self.lowercase {
+                text.to_lowercase()
+            } else {
+                text
+            },
+            source: None,
+            id: None,
+            length: None,
+            index: None,
+        })
     }
 }
 
@@ -61,6 +246,13 @@ impl Quoter {
 pub struct Quote {
     pub text: String,
     pub source: Option<String>,
+    #[serde(default)]
+    pub id: Option<u64>,
+    #[serde(skip)]
+    pub length: Option<Length>,
+    /// Position in the collection it was taken from
+    #[serde(skip)]
+    pub index: Option<usize>,
 }
 
 pub fn get_config_folder() -> Result<PathBuf> {
@@ -81,13 +273,244 @@ pub fn get_config_folder() -> Result<PathBuf> {
     Ok(path)
 }
 
-pub fn get_quoter() -> Result<Quoter> {
+fn get_quotes_folder() -> Result<PathBuf> {
+    let mut path = get_config_folder()?;
+    path.push("quotes");
+    Ok(path)
+}
+
+/// Reads the collection called `name` from the quotes folder. Without a name
+/// it's the english one, or quotes.json when there's none.
+pub fn get_quoter(name: Option<&str>) -> Result<Quoter> {
+    let mut path = get_quotes_folder()?;
+    path.push(format!("{}.json", name.unwrap_or(DEFAULT_COLLECTION)));
+    if !path.exists() {
+        if let Some(name) = name {
+            return Err(anyhow!("There's no quote collection called {}", name));
+        }
+        path = get_config_folder()?;
+        path.push("quotes.json");
+        if !path.exists() {
+            return Err(anyhow!("There's no quotes.json file"));
+        }
+    }
+    let r = fs::File::open(&path)?;
+    let mut quoter: Quoter = serde_json::from_reader(r)?;
+    quoter.name = collection_name(&path)?;
+    quoter.seen = load_seen()?.remove(&quoter.name).unwrap_or_default();
+    Ok(quoter)
+}
+
+/// Collections in the quotes folder are named after their file, while the
+/// lone quotes.json keeps its file name.
+fn collection_name(path: &Path) -> Result<String> {
+    if path.parent() == Some(get_quotes_folder()?.as_path()) {
+        return Ok(path.file_stem().unwrap().to_string_lossy().to_string());
+    }
+    Ok(String::from("quotes.json"))
+}
+
+fn get_seen_path() -> Result<PathBuf> {
+    let mut path = get_config_folder()?;
+    path.push("recent.json");
+    Ok(path)
+}
+
+/// The quotes typed from each collection. The file only spares you from
+/// repeating quotes, so when it can't be read it's as if it was empty.
+fn load_seen() -> Result<HashMap<String, Seen>> {
+    let path = get_seen_path()?;
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let r = fs::File::open(path)?;
+    Ok(serde_json::from_reader(r).unwrap_or_default())
+}
+
+/// Writes to a temporary file first, so the old file stays whole if writing
+/// gets cut short.
+fn save_seen(name: &str, seen: &Seen) -> Result<()> {
+    let mut all = load_seen()?;
+    all.insert(name.to_string(), seen.clone());
+    let path = get_seen_path()?;
+    let temp = path.with_extension("json.tmp");
+    let file = fs::File::create(&temp)?;
+    serde_json::to_writer(&file, &all)?;
+    file.sync_all()?;
+    fs::rename(temp, path)?;
+    Ok(())
+}
+
+pub struct Collection {
+    pub name: String,
+    pub language: Option<String>,
+    pub quotes: usize,
+}
+
+/// Every collection in the quotes folder, sorted by name, followed by
+/// quotes.json if there's one.
+pub fn get_collections() -> Result<Vec<Collection>> {
+    let mut paths = Vec::new();
+    let folder = get_quotes_folder()?;
+    if folder.is_dir() {
+        for entry in folder.read_dir()? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    let mut legacy = get_config_folder()?;
+    legacy.push("quotes.json");
+    if legacy.exists() {
+        paths.push(legacy);
+    }
+
+    let mut collections = Vec::new();
+    for path in paths {
+        let r = fs::File::open(&path)?;
+        let quoter: Quoter = serde_json::from_reader(r)
+            .map_err(|e| anyhow!("Can't read {}: {}", path.display(), e))?;
+        collections.push(Collection {
+            name: collection_name(&path)?,
+            language: quoter.language,
+            quotes: quoter.quotes.len(),
+        });
+    }
+    Ok(collections)
+}
+
+pub fn get_word_list() -> Result<WordList> {
     let mut config_folder = get_config_folder()?;
-    config_folder.push("quotes.json");
+    config_folder.push("words.json");
     if !config_folder.exists() {
-        return Err(anyhow!("There's no quotes.json file"));
+        return Err(anyhow!("There's no words.json file"));
     }
     let r = fs::File::open(&config_folder)?;
-    let quoter = serde_json::from_reader(r)?;
-    Ok(quoter)
+    let word_list = serde_json::from_reader(r)?;
+    Ok(word_list)
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// Lets backspace move back into the previous word when mistakes were
+    /// made while typing it
+    pub backspace_into_mistakes: bool,
+    /// Lets letters match regardless of their accents, so "e" counts as "é"
+    pub ignore_diacritics: bool,
+    /// Keyboard layout to type in when the system one is QWERTY
+    pub layout: Option<String>,
+    /// Key chords like "ctrl+w" mapped to what they do, on top of the default
+    /// ones
+    pub keybindings: HashMap<String, Action>,
+    /// Goes through every quote of a collection before repeating any
+    pub unseen_first: bool,
+    /// Seconds of countdown before the run starts. With 0 the clock starts
+    /// on the first key press instead
+    pub countdown: u64,
+    /// Seconds without key presses after which the run is considered idle,
+    /// 0 to never do so
+    pub idle_timeout: u64,
+    pub idle_action: IdleAction,
+}
+
+/// What happens to a run nobody is typing on.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleAction {
+    /// Stops the clock until the next key press
+    #[default]
+    Pause,
+    /// Ends the run without saving it
+    Abort,
+}
+
+/// Reads config.json, falling back to the defaults when there's none.
+pub fn get_settings() -> Result<Settings> {
+    let mut config_folder = get_config_folder()?;
+    config_folder.push("config.json");
+    if !config_folder.exists() {
+        return Ok(Settings::default());
+    }
+    let r = fs::File::open(&config_folder)?;
+    let settings = serde_json::from_reader(r)?;
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quoter(json: &str) -> Quoter {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn groups_quotes_by_length() {
+        let quoter = quoter(
+            r#"{
+                "groups": [[0, 5], [6, 10], [11, 15], [16, 20]],
+                "quotes": [
+                    {"text": "abc", "source": null, "id": 1},
+                    {"text": "abcdefgh", "source": null, "id": 2},
+                    {"text": "abcdefghijklm", "source": null, "id": 3},
+                    {"text": "abcdefghijklmnopq", "source": null, "id": 4},
+                    {"text": "abcdefghijklmnopqrstuvwxyz", "source": null, "id": 5}
+                ]
+            }"#,
+        );
+        let lengths: Vec<Option<Length>> =
+            quoter.quotes.iter().map(|q| quoter.length_of(q)).collect();
+        assert_eq!(
+            lengths,
+            [
+                Some(Length::Short),
+                Some(Length::Medium),
+                Some(Length::Long),
+                Some(Length::Huge),
+                Some(Length::Huge)
+            ]
+        );
+    }
+
+    #[test]
+    fn uses_monkeytype_groups_by_default() {
+        let mut quoter = quoter(r#"{"quotes": [{"text": "abc", "source": null}]}"#);
+        assert_eq!(quoter.groups, DEFAULT_GROUPS);
+        assert_eq!(quoter.get(Some(Length::Short)).unwrap().text, "abc");
+        assert!(quoter.get(Some(Length::Long)).is_err());
+    }
+
+    #[test]
+    fn skips_recent_quotes() {
+        let mut quoter = quoter(
+            r#"{"quotes": [
+                {"text": "abc", "source": null, "id": 1},
+                {"text": "def", "source": null}
+            ]}"#,
+        );
+        quoter.seen.recent = vec![String::from("1")];
+        for _ in 0..10 {
+            assert_eq!(quoter.get(None).unwrap().text, "def");
+        }
+        quoter.seen.recent = vec![String::from("1"), String::from("#1")];
+        assert!(quoter.get(None).is_ok());
+    }
+
+    #[test]
+    fn picks_the_least_typed_quotes_first() {
+        let mut quoter = quoter(
+            r#"{"quotes": [
+                {"text": "abc", "source": null, "id": 1},
+                {"text": "def", "source": null, "id": 2}
+            ]}"#,
+        );
+        quoter.set_unseen_first(true);
+        quoter.seen.counts = HashMap::from([(String::from("1"), 2), (String::from("2"), 1)]);
+        for _ in 0..10 {
+            assert_eq!(quoter.get(None).unwrap().id, Some(2));
+        }
+    }
 }
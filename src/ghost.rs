@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use crate::{
+    config::Settings,
+    history::Log,
+    state::{Key, Keystroke},
+    text::{grapheme_count, graphemes, is_prefix, normalize},
+};
+
+/// A second caret to race against.
+pub enum Ghost {
+    /// Types at a constant speed, in WPM
+    Pace(f64),
+    /// Follows a recorded run. Holds the position reached after each of its
+    /// keystrokes.
+    Replay(Vec<(Duration, (usize, usize))>),
+}
+
+impl Ghost {
+    pub fn from_log(log: &Log) -> Ghost {
+        let text = normalize(&log.text);
+        let quote: Vec<&str> = text.split_whitespace().collect();
+        return Ghost::from_keystrokes(&quote, &log.keystrokes, &log.settings);
+    }
+
+    /// Plays `keystrokes` back against `quote` to find out where the caret of
+    /// the recorded run was after every key.
+    fn from_keystrokes(quote: &[&str], keystrokes: &[Keystroke], settings: &Settings) -> Ghost {
+        let mut current = 0;
+        let mut buffer = String::new();
+        let mut mistyped = vec![false; quote.len()];
+        let mut positions = Vec::new();
+        for k in keystrokes {
+            if current >= quote.len() {
+                break;
+            }
+            let can_step_back = settings.backspace_into_mistakes
+                && buffer.is_empty()
+                && current > 0
+                && mistyped[current - 1];
+            let is_word_typed = grapheme_count(&buffer) == grapheme_count(quote[current])
+                && is_prefix(&buffer, quote[current], settings.ignore_diacritics);
+            match k.key {
+                Key::Press(' ') if is_word_typed => {
+                    current += 1;
+                    buffer.clear();
+                }
+                Key::Press(c) => {
+                    buffer.push(c);
+                    buffer = normalize(&buffer);
+                    mistyped[current] |= !k.correct;
+                }
+                Key::Backspace if can_step_back => {
+                    current -= 1;
+                    buffer = quote[current].to_string();
+                }
+                Key::Backspace => {
+                    let len = graphemes(&buffer)
+                        .last()
+                        .map_or(0, |g| buffer.len() - g.len());
+                    buffer.truncate(len);
+                }
+                Key::DeleteWord => {
+                    if can_step_back {
+                        current -= 1;
+                    }
+                    buffer.clear();
+                }
+            }
+            positions.push((k.at, (current, grapheme_count(&buffer))));
+        }
+        return Ghost::Replay(positions);
+    }
+
+    /// Word and character the ghost is on after `elapsed`.
+    pub fn position(&self, quote: &[String], elapsed: Duration) -> (usize, usize) {
+        match self {
+            Ghost::Pace(wpm) => {
+                let mut chars = (elapsed.as_secs_f64() * wpm * 5.0 / 60.0) as usize;
+                for (i, word) in quote.iter().enumerate() {
+                    let len = grapheme_count(word);
+                    if chars <= len {
+                        return (i, chars);
+                    }
+                    chars -= len + 1;
+                }
+                let last = quote.len() - 1;
+                return (last, grapheme_count(&quote[last]));
+            }
+            Ghost::Replay(positions) => positions
+                .iter()
+                .take_while(|(at, _)| *at <= elapsed)
+                .last()
+                .map(|(_, p)| *p)
+                .unwrap_or((0, 0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keystrokes(keys: &[(Key, bool)]) -> Vec<Keystroke> {
+        keys.iter()
+            .enumerate()
+            .map(|(i, (key, correct))| Keystroke {
+                at: Duration::from_secs(i as u64 + 1),
+                key: *key,
+                expected: None,
+                correct: *correct,
+                held: None,
+            })
+            .collect()
+    }
+
+    fn last_position(ghost: &Ghost) -> (usize, usize) {
+        let Ghost::Replay(positions) = ghost else {
+            panic!("Expected a replay ghost");
+        };
+        return positions.last().unwrap().1;
+    }
+
+    #[test]
+    fn follows_the_recorded_caret() {
+        let keys = keystrokes(&[
+            (Key::Press('a'), true),
+            (Key::Press('x'), false),
+            (Key::Backspace, true),
+            (Key::Press('b'), true),
+            (Key::Press(' '), true),
+            (Key::Press('c'), true),
+        ]);
+        let ghost = Ghost::from_keystrokes(&["ab", "cd"], &keys, &Settings::default());
+        let quote = vec![String::from("ab"), String::from("cd")];
+        assert_eq!(ghost.position(&quote, Duration::ZERO), (0, 0));
+        assert_eq!(ghost.position(&quote, Duration::from_secs(2)), (0, 2));
+        assert_eq!(ghost.position(&quote, Duration::from_secs(3)), (0, 1));
+        assert_eq!(ghost.position(&quote, Duration::from_secs(10)), (1, 1));
+    }
+
+    #[test]
+    fn steps_back_into_mistyped_words() {
+        let keys = keystrokes(&[
+            (Key::Press('a'), true),
+            (Key::Press('x'), false),
+            (Key::Backspace, true),
+            (Key::Press('b'), true),
+            (Key::Press(' '), true),
+            (Key::Backspace, true),
+        ]);
+        let settings = Settings {
+            backspace_into_mistakes: true,
+            ..Settings::default()
+        };
+        let ghost = Ghost::from_keystrokes(&["ab", "cd"], &keys, &settings);
+        assert_eq!(last_position(&ghost), (0, 2));
+
+        // Without the setting backspace stops at the start of the word
+        let ghost = Ghost::from_keystrokes(&["ab", "cd"], &keys, &Settings::default());
+        assert_eq!(last_position(&ghost), (1, 0));
+    }
+
+    #[test]
+    fn doesnt_step_back_into_correct_words() {
+        let keys = keystrokes(&[
+            (Key::Press('a'), true),
+            (Key::Press('b'), true),
+            (Key::Press(' '), true),
+            (Key::DeleteWord, true),
+        ]);
+        let settings = Settings {
+            backspace_into_mistakes: true,
+            ..Settings::default()
+        };
+        let ghost = Ghost::from_keystrokes(&["ab", "cd"], &keys, &settings);
+        assert_eq!(last_position(&ghost), (1, 0));
+    }
+}
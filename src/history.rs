@@ -0,0 +1,172 @@
+use std::{
+    fmt::Display,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Length, Settings, get_config_folder},
+    state::Keystroke,
+    text::normalize,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Quote,
+    Time(u64),
+    Words(u64),
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mode::Quote => f.write_str("quote"),
+            Mode::Time(secs) => f.write_fmt(format_args!("time {}", secs)),
+            Mode::Words(count) => f.write_fmt(format_args!("words {}", count)),
+        }
+    }
+}
+
+/// A completed run as stored in the history file.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Record {
+    /// Also names the keystroke log of the run
+    #[serde(default)]
+    pub id: u64,
+    /// Seconds since the UNIX epoch at the end of the run
+    pub timestamp: u64,
+    pub mode: Mode,
+    #[serde(default)]
+    pub length: Option<Length>,
+    pub quote_id: Option<u64>,
+    pub source: Option<String>,
+    /// Net WPM, counting only the characters typed correctly
+    pub wpm: f64,
+    /// WPM counting every typed character, mistakes included
+    #[serde(default)]
+    pub raw_wpm: f64,
+    /// How steady the typing speed was, from 0 to 100
+    #[serde(default)]
+    pub consistency: f64,
+    pub accuracy: f64,
+    pub mistakes: u32,
+    pub duration_ms: u64,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn get_history_path() -> Result<PathBuf> {
+    let mut path = get_config_folder()?;
+    path.push("history.jsonl");
+    Ok(path)
+}
+
+/// Appends a record to the history file, one JSON object per line.
+pub fn save_record(record: &Record) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(get_history_path()?)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// Reads back every record in the history file, oldest first. Lines that
+/// can't be parsed are skipped.
+pub fn load_records() -> Result<Vec<Record>> {
+    let path = get_history_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let records = fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    Ok(records)
+}
+
+/// Finds the record of the run with the given ID.
+pub fn find_record(id: u64) -> Result<Record> {
+    load_records()?
+        .into_iter()
+        .find(|r| r.id == id)
+        .ok_or(anyhow!("There's no run with ID {}", id))
+}
+
+/// ID for the next record, one more than the highest one in the history.
+pub fn next_id() -> Result<u64> {
+    Ok(load_records()?.iter().map(|r| r.id).max().unwrap_or(0) + 1)
+}
+
+/// Everything needed to replay a run.
+#[derive(Serialize, Deserialize)]
+pub struct Log {
+    pub text: String,
+    pub keystrokes: Vec<Keystroke>,
+    /// Settings the run was typed with
+    #[serde(default)]
+    pub settings: Settings,
+}
+
+fn get_log_path(id: u64) -> Result<PathBuf> {
+    let mut path = get_config_folder()?;
+    path.push("logs");
+    fs::create_dir_all(&path)?;
+    path.push(format!("{}.json", id));
+    Ok(path)
+}
+
+/// Saves the log of the run with the given record ID.
+pub fn save_log(id: u64, log: &Log) -> Result<()> {
+    let file = fs::File::create(get_log_path(id)?)?;
+    serde_json::to_writer(file, log)?;
+    Ok(())
+}
+
+pub fn load_log(id: u64) -> Result<Log> {
+    let path = get_log_path(id)?;
+    if !path.exists() {
+        return Err(anyhow!("There's no keystroke log for run {}", id));
+    }
+    let r = fs::File::open(path)?;
+    Ok(serde_json::from_reader(r)?)
+}
+
+/// Log of the fastest quote run typed on exactly `text`, if there's one.
+pub fn best_log(text: &str, quote_id: Option<u64>, source: &Option<String>) -> Result<Option<Log>> {
+    let mut records: Vec<Record> = load_records()?
+        .into_iter()
+        .filter(|r| r.mode == Mode::Quote && r.quote_id == quote_id && r.source == *source)
+        .collect();
+    records.sort_by(|a, b| b.wpm.total_cmp(&a.wpm));
+    // Logs keep the text as it was typed, which is normalized
+    let text = normalize(text);
+    for r in records {
+        if let Ok(log) = load_log(r.id)
+            && normalize(&log.text) == text
+        {
+            return Ok(Some(log));
+        }
+    }
+    Ok(None)
+}
+
+/// Best WPM among the records sharing the given mode and length group.
+pub fn personal_best(records: &[Record], mode: Mode, length: Option<Length>) -> Option<f64> {
+    records
+        .iter()
+        .filter(|r| r.mode == mode && r.length == length)
+        .map(|r| r.wpm)
+        .reduce(f64::max)
+}
@@ -1,37 +1,221 @@
-use std::{error::Error, time::Duration};
+use std::{collections::HashMap, error::Error, time::Duration};
 
-use crossterm::event::{KeyCode, KeyModifiers, poll, read};
+use anyhow::{Result, anyhow};
+use crossterm::event::{KeyCode, KeyEventKind, KeyEventState, KeyModifiers, poll, read};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Sender;
 
-use crate::app::TICK_RATE;
+use crate::{app::TICK_RATE, layout::Layout, state::Key};
 
 #[derive(PartialEq)]
 pub enum Event {
     Terminate,
     KeyPress(char),
+    /// Only reported by terminals supporting the kitty keyboard protocol
+    KeyRelease(char),
     Backspace,
+    DeleteWord,
+    Confirm,
     Render,
     ForceRender,
+    /// A key coming from a recorded run
+    Replay(Key),
+    Paste,
+    Pause,
+    Resume,
+    /// Start over on the same text
+    Restart,
+    /// Start over on a new text
+    NextQuote,
+    TogglePause,
+    /// Show or hide the live WPM and accuracy
+    ToggleStats,
 }
 
-// TODO
-// - [ ] Pause on focus lost
-// - [ ] Invalidate on paste
-pub async fn handle_input(sender: &Sender<Event>) -> Result<(), Box<dyn Error>> {
+/// Something a key chord can be bound to.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    Restart,
+    NextQuote,
+    DeleteWord,
+    Pause,
+    ToggleStats,
+}
+
+impl Action {
+    fn event(self) -> Event {
+        match self {
+            Action::Quit => Event::Terminate,
+            Action::Restart => Event::Restart,
+            Action::NextQuote => Event::NextQuote,
+            Action::DeleteWord => Event::DeleteWord,
+            Action::Pause => Event::TogglePause,
+            Action::ToggleStats => Event::ToggleStats,
+        }
+    }
+}
+
+const DEFAULT_BINDINGS: [(&str, Action); 7] = [
+    ("ctrl+c", Action::Quit),
+    ("tab", Action::Restart),
+    ("ctrl+n", Action::NextQuote),
+    ("ctrl+backspace", Action::DeleteWord),
+    ("alt+backspace", Action::DeleteWord),
+    ("ctrl+p", Action::Pause),
+    ("ctrl+t", Action::ToggleStats),
+];
+
+type Chord = (KeyCode, KeyModifiers);
+
+pub struct Keybindings {
+    chords: HashMap<Chord, Action>,
+}
+
+impl Keybindings {
+    /// The default bindings, overridden or extended by the `custom` ones.
+    pub fn new(custom: &HashMap<String, Action>) -> Result<Keybindings> {
+        let mut chords = HashMap::new();
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|(chord, action)| (*chord, *action))
+            .chain(
+                custom
+                    .iter()
+                    .map(|(chord, action)| (chord.as_str(), *action)),
+            );
+        for (chord, action) in bindings {
+            chords.insert(parse_chord(chord)?, action);
+        }
+        return Ok(Keybindings { chords });
+    }
+
+    fn get(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<Action> {
+        let code = match code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_lowercase().next().unwrap_or(c)),
+            code => code,
+        };
+        self.chords.get(&(code, modifiers)).copied()
+    }
+
+    /// The chords bound to `action`, written like "Ctrl+N" and joined with
+    /// "or", if there's any.
+    pub fn describe(&self, action: Action) -> Option<String> {
+        let mut chords: Vec<String> = self
+            .chords
+            .iter()
+            .filter(|(_, a)| **a == action)
+            .map(|(chord, _)| describe_chord(chord))
+            .collect();
+        if chords.is_empty() {
+            return None;
+        }
+        chords.sort();
+        return Some(chords.join(" or "));
+    }
+}
+
+fn describe_chord((code, modifiers): &Chord) -> String {
+    let mut name = String::new();
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        name.push_str("Ctrl+");
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        name.push_str("Alt+");
+    }
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        name.push_str("Shift+");
+    }
+    match code {
+        KeyCode::Char(c) if *c != ' ' => name.extend(c.to_uppercase()),
+        code => name.push_str(&code.to_string()),
+    }
+    return name;
+}
+
+/// Reads chords like "ctrl+w", "esc" or "alt+backspace".
+fn parse_chord(chord: &str) -> Result<Chord> {
+    let lowercase = chord.to_lowercase();
+    let mut parts: Vec<&str> = lowercase.split('+').collect();
+    // The plus key itself
+    if lowercase.ends_with("++") {
+        parts.truncate(parts.len() - 2);
+        parts.push("+");
+    }
+    let key = parts.pop().unwrap_or_default();
+    let mut modifiers = KeyModifiers::NONE;
+    for m in parts {
+        modifiers |= match m {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return Err(anyhow!("Unknown modifier {} in key binding {}", m, chord)),
+        };
+    }
+    let code = match key {
+        "backspace" => KeyCode::Backspace,
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "esc" | "escape" => KeyCode::Esc,
+        "space" => KeyCode::Char(' '),
+        "delete" => KeyCode::Delete,
+        k if k.chars().count() == 1 => KeyCode::Char(k.chars().next().unwrap()),
+        k if k.starts_with('f') && k[1..].parse::<u8>().is_ok() => {
+            KeyCode::F(k[1..].parse().unwrap())
+        }
+        _ => return Err(anyhow!("Unknown key {} in key binding {}", key, chord)),
+    };
+    return Ok((code, modifiers));
+}
+
+pub async fn handle_input(
+    sender: &Sender<Event>,
+    layout: &Layout,
+    keybindings: &Keybindings,
+) -> Result<(), Box<dyn Error>> {
     if poll(Duration::from_millis(TICK_RATE))? {
         match read()? {
-            //crossterm::event::Event::FocusGained => todo!(),
-            //crossterm::event::Event::FocusLost => todo!(),
-            //crossterm::event::Event::Paste(_) => todo!(),
+            crossterm::event::Event::FocusGained => sender.send(Event::Resume).await?,
+            crossterm::event::Event::FocusLost => sender.send(Event::Pause).await?,
+            crossterm::event::Event::Paste(_) => sender.send(Event::Paste).await?,
             crossterm::event::Event::Resize(_, _) => sender.send(Event::ForceRender).await?,
-            crossterm::event::Event::Key(key_event) => {
-                match (key_event.code, key_event.modifiers) {
-                    (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
-                        sender.send(Event::Terminate).await?
+            // Shift, Ctrl and the like are reported on their own when all keys
+            // come as escape codes
+            crossterm::event::Event::Key(key_event)
+                if matches!(key_event.code, KeyCode::Modifier(_)) => {}
+            crossterm::event::Event::Key(mut key_event) => {
+                // Letters reported as escape codes don't follow Caps Lock
+                if key_event.state.contains(KeyEventState::CAPS_LOCK)
+                    && let KeyCode::Char(c) = key_event.code
+                {
+                    key_event.code = KeyCode::Char(c.to_uppercase().next().unwrap_or(c));
+                }
+                match key_event.kind {
+                    KeyEventKind::Release => {
+                        if let KeyCode::Char(c) = key_event.code {
+                            sender.send(Event::KeyRelease(layout.map(c))).await?
+                        }
                     }
-                    (KeyCode::Backspace, _) => sender.send(Event::Backspace).await?,
-                    (KeyCode::Char(c), _) => sender.send(Event::KeyPress(c)).await?,
-                    _ => (),
+                    // Holding a key down shouldn't type it over and over
+                    KeyEventKind::Repeat if matches!(key_event.code, KeyCode::Char(_)) => (),
+                    _ => match keybindings.get(key_event.code, key_event.modifiers) {
+                        Some(action) => sender.send(action.event()).await?,
+                        None => match key_event.code {
+                            // Chords like Ctrl+Shift+Backspace that aren't bound on their own
+                            KeyCode::Backspace
+                                if key_event
+                                    .modifiers
+                                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+                            {
+                                sender.send(Event::DeleteWord).await?
+                            }
+                            KeyCode::Backspace => sender.send(Event::Backspace).await?,
+                            KeyCode::Enter => sender.send(Event::Confirm).await?,
+                            KeyCode::Char(c) => sender.send(Event::KeyPress(layout.map(c))).await?,
+                            _ => (),
+                        },
+                    },
                 }
             }
             _ => (),
@@ -39,3 +223,50 @@ pub async fn handle_input(sender: &Sender<Event>) -> Result<(), Box<dyn Error>>
     }
     return Ok(());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_chords() {
+        assert_eq!(
+            parse_chord("Ctrl+W").unwrap(),
+            (KeyCode::Char('w'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            parse_chord("ctrl++").unwrap(),
+            (KeyCode::Char('+'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            parse_chord("f12").unwrap(),
+            (KeyCode::F(12), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            parse_chord("ctrl+alt+backspace").unwrap(),
+            (
+                KeyCode::Backspace,
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_chords() {
+        assert!(parse_chord("hyper+x").is_err());
+        assert!(parse_chord("ctrl+pageup").is_err());
+    }
+
+    #[test]
+    fn describes_bound_chords() {
+        let custom = HashMap::from([(String::from("f2"), Action::NextQuote)]);
+        let keybindings = Keybindings::new(&custom).unwrap();
+        assert_eq!(
+            keybindings.describe(Action::NextQuote).as_deref(),
+            Some("Ctrl+N or F2")
+        );
+        let custom = HashMap::from([(String::from("tab"), Action::Quit)]);
+        let keybindings = Keybindings::new(&custom).unwrap();
+        assert_eq!(keybindings.describe(Action::Restart), None);
+    }
+}
@@ -0,0 +1,101 @@
+use std::{collections::HashMap, fs};
+
+use anyhow::{Result, anyhow};
+
+use crate::config::get_config_folder;
+
+const QWERTY: [&str; 2] = [
+    r#"`1234567890-=qwertyuiop[]\asdfghjkl;'zxcvbnm,./"#,
+    r#"~!@#$%^&*()_+QWERTYUIOP{}|ASDFGHJKL:"ZXCVBNM<>?"#,
+];
+const DVORAK: [&str; 2] = [
+    r#"`1234567890[]',.pyfgcrl/=\aoeuidhtns-;qjkxbmwvz"#,
+    r#"~!@#$%^&*(){}"<>PYFGCRL?+|AOEUIDHTNS_:QJKXBMWVZ"#,
+];
+const COLEMAK: [&str; 2] = [
+    r#"`1234567890-=qwfpgjluy;[]\arstdhneio'zxcvbkm,./"#,
+    r#"~!@#$%^&*()_+QWFPGJLUY:{}|ARSTDHNEIO"ZXCVBKM<>?"#,
+];
+const WORKMAN: [&str; 2] = [
+    r#"`1234567890-=qdrwbjfup;[]\ashtgyneoi'zxmcvkl,./"#,
+    r#"~!@#$%^&*()_+QDRWBJFUP:{}|ASHTGYNEOI"ZXMCVKL<>?"#,
+];
+
+/// Translates the characters a QWERTY keyboard sends into the ones the same
+/// keys would type on another layout.
+#[derive(Default, Clone)]
+pub struct Layout {
+    keys: HashMap<char, char>,
+}
+
+impl Layout {
+    /// One of the built in layouts, or the mapping in `layouts/<name>.json`
+    /// inside the config folder.
+    pub fn load(name: &str) -> Result<Layout> {
+        let target = match name {
+            "qwerty" => return Ok(Layout::default()),
+            "dvorak" => DVORAK,
+            "colemak" => COLEMAK,
+            "workman" => WORKMAN,
+            _ => return Layout::from_file(name),
+        };
+        let keys = QWERTY
+            .iter()
+            .zip(target)
+            .flat_map(|(from, to)| from.chars().zip(to.chars()))
+            .collect();
+        return Ok(Layout { keys });
+    }
+
+    /// Reads a JSON object mapping each QWERTY character to the one it should
+    /// type instead. Characters left out type themselves.
+    fn from_file(name: &str) -> Result<Layout> {
+        let mut path = get_config_folder()?;
+        path.push("layouts");
+        path.push(format!("{}.json", name));
+        if !path.exists() {
+            return Err(anyhow!("There's no layout called {}", name));
+        }
+        let r = fs::File::open(path)?;
+        let keys = serde_json::from_reader(r)?;
+        return Ok(Layout { keys });
+    }
+
+    pub fn map(&self, c: char) -> char {
+        self.keys.get(&c).copied().unwrap_or(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layouts_cover_every_key() {
+        for layout in [DVORAK, COLEMAK, WORKMAN] {
+            for (from, to) in QWERTY.iter().zip(layout) {
+                assert_eq!(from.chars().count(), to.chars().count());
+            }
+        }
+    }
+
+    #[test]
+    fn qwerty_types_itself() {
+        let layout = Layout::load("qwerty").unwrap();
+        assert_eq!(layout.map('k'), 'k');
+        assert_eq!(layout.map(';'), ';');
+    }
+
+    #[test]
+    fn maps_built_in_layouts() {
+        let dvorak = Layout::load("dvorak").unwrap();
+        assert_eq!(dvorak.map('k'), 't');
+        assert_eq!(dvorak.map('K'), 'T');
+        assert_eq!(dvorak.map('q'), '\'');
+        assert_eq!(dvorak.map('é'), 'é');
+        let colemak = Layout::load("colemak").unwrap();
+        assert_eq!(colemak.map('j'), 'n');
+        let workman = Layout::load("workman").unwrap();
+        assert_eq!(workman.map('e'), 'r');
+    }
+}
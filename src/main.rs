@@ -3,8 +3,13 @@
 mod app;
 pub mod config;
 pub mod error;
+pub mod ghost;
+pub mod history;
 pub mod input;
+pub mod layout;
 pub mod state;
+pub mod stats;
+pub mod text;
 
 use std::{
     fs::read_to_string,
@@ -14,24 +19,95 @@ use std::{
 
 use anyhow::Result;
 use app::App;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use rand::Rng;
 
-use crate::config::{Quote, get_quoter};
+use crate::{
+    config::{Length, Quote, get_collections, get_quoter, get_settings, get_word_list},
+    ghost::Ghost,
+    history::{Mode, best_log, find_record, load_log, load_records},
+    stats::print_stats,
+};
+
+const SEARCH_PREVIEW_LEN: usize = 60;
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Summarizes the runs saved in your history
+    Stats,
+    /// Lists the quote collections in the quotes folder
+    Collections,
+    /// Lists the quotes whose text or source contain the given term
+    Search {
+        term: String,
+        /// Search the given collection in the quotes folder
+        #[arg(long, value_name = "NAME")]
+        language: Option<String>,
+    },
+    /// Plays back a past run
+    Replay {
+        /// ID of the run, shown on its result screen
+        id: u64,
+        /// How many times faster than real time to play it back
+        #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=4))]
+        speed: u32,
+    },
+}
+
+#[derive(clap::Args)]
 struct Args {
     /// Turns all text into lowercase (NOOB mode)
-    #[arg(short, long)]
+    #[arg(short = 'L', long)]
     lower: bool,
+    /// Pick a quote from the short length group
     #[arg(short, long)]
     short: bool,
+    /// Pick a quote from the medium length group
     #[arg(short, long)]
     medium: bool,
+    /// Pick a quote from the long length group
     #[arg(short, long)]
     long: bool,
-    #[arg(short, long)]
+    /// Pick a quote from the huge length group
+    #[arg(short = 'H', long)]
     huge: bool,
+    /// Type for the given amount of seconds instead of until the quote ends
+    #[arg(short, long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    time: Option<u64>,
+    /// Type the given amount of random words taken from words.json
+    #[arg(
+        short,
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["short", "medium", "long", "huge", "time", "quote"],
+    )]
+    words: Option<u64>,
+    /// Race against a caret typing at the given WPM
+    #[arg(short, long, value_name = "WPM", value_parser = clap::value_parser!(u64).range(1..))]
+    pace: Option<u64>,
+    /// Race against your best run on the same quote, if there's one
+    #[arg(short, long, conflicts_with_all = ["pace", "time", "words"])]
+    ghost: bool,
+    /// Take quotes from the given collection in the quotes folder
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["words", "quote"])]
+    language: Option<String>,
+    /// Type the quote with the given ID, as listed by search
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["short", "medium", "long", "huge", "words", "quote"],
+    )]
+    id: Option<u64>,
     quote: Option<String>,
 }
 
@@ -53,25 +129,52 @@ fn generate_quotes(path: &Path) -> Result<Vec<String>> {
     return Ok(ris);
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+fn requested_length(args: &Args) -> Option<Length> {
+    if args.short {
+        return Some(Length::Short);
+    } else if args.medium {
+        return Some(Length::Medium);
+    } else if args.long {
+        return Some(Length::Long);
+    } else if args.huge {
+        return Some(Length::Huge);
+    }
+    return None;
+}
+
+async fn type_quote(args: Args) -> Result<()> {
+    let settings = get_settings()?;
+    // New texts only come from the config folder, not from stdin or files
+    let mut quoter = None;
+    let mut word_list = None;
     let mut quote = if !stdin().is_terminal() {
         let mut b = Vec::new();
         stdin().read_to_end(&mut b).unwrap();
         Quote {
             text: String::from_utf8(b)?,
             source: None,
+            id: None,
+            length: None,
+            index: None,
         }
     } else if let Some(q) = &args.quote {
         let path = Path::new(q);
         let mut quotes = generate_quotes(path).unwrap();
-        let mut rng = rand::rand_core::UnwrapErr(rand::rngs::SysRng::default());
+        let mut rng = rand::rand_core::UnwrapErr(rand::rngs::SysRng);
         let chosen = rng.next_u64() as usize;
         Quote {
             text: quotes.remove(chosen),
             source: None,
+            id: None,
+            length: None,
+            index: None,
         }
+    } else if let Some(count) = args.words {
+        let mut list = get_word_list()?;
+        list.set_lowercase(args.lower);
+        let quote = list.get_words(count as usize)?;
+        word_list = Some(list);
+        quote
     } else {
         let mut specifier = 0;
         if args.short {
@@ -89,27 +192,116 @@ async fn main() -> Result<()> {
         if specifier > 1 {
             panic!("You can't use more than one quote length specifier");
         }
-        let mut quoter = get_quoter()?;
-        if args.short {
-            quoter.get_short()?
-        } else if args.medium {
-            quoter.get_medium()?
-        } else if args.long {
-            quoter.get_long()?
-        } else if args.huge {
-            quoter.get_huge()?
-        } else {
-            quoter.get_random()?
-        }
+        let mut collection = get_quoter(args.language.as_deref())?;
+        collection.set_lowercase(args.lower);
+        collection.set_unseen_first(settings.unseen_first);
+        let quote = match args.id {
+            Some(id) => collection.get_by_id(id)?,
+            None => collection.get(requested_length(&args))?,
+        };
+        quoter = Some(collection);
+        quote
     };
 
     if args.lower {
         quote.text = quote.text.to_lowercase();
     }
 
-    // TODO Add more options to choose quotes
-    let mut app = App::new(&quote);
+    let mut app = match (args.time, args.words) {
+        (Some(secs), _) => App::timed(&quote, secs),
+        (None, Some(count)) => App::new(&quote, Mode::Words(count)),
+        (None, None) => App::new(&quote, Mode::Quote),
+    };
+
+    if let Some(quoter) = quoter {
+        app.set_quoter(quoter, requested_length(&args));
+    }
+    if let Some(word_list) = word_list {
+        app.set_word_list(word_list);
+    }
+
+    app.set_settings(settings);
+    if let Some(wpm) = args.pace {
+        app.set_ghost(Ghost::Pace(wpm as f64));
+    } else if args.ghost
+        && let Some(log) = best_log(&quote.text, quote.id, &quote.source)?
+    {
+        app.set_ghost(Ghost::from_log(&log));
+    }
+
+    app.start().await?;
+    return Ok(());
+}
+
+fn print_collections() -> Result<()> {
+    let collections = get_collections()?;
+    if collections.is_empty() {
+        println!("There are no quote collections yet");
+        return Ok(());
+    }
+    for c in collections {
+        println!(
+            "{:<20} {:<12} {} {}",
+            c.name,
+            c.language.unwrap_or_default(),
+            c.quotes,
+            if c.quotes == 1 { "quote" } else { "quotes" }
+        );
+    }
+    return Ok(());
+}
+
+fn print_search(term: &str, language: Option<&str>) -> Result<()> {
+    let quotes = get_quoter(language)?.search(term);
+    if quotes.is_empty() {
+        println!("No quote matches {}", term);
+        return Ok(());
+    }
+    for q in quotes {
+        let mut preview: String = q.text.chars().take(SEARCH_PREVIEW_LEN).collect();
+        if preview.len() < q.text.len() {
+            preview.push('…');
+        }
+        println!(
+            "{:>6}  {:<6}  {}\n        {}",
+            q.id.map(|id| id.to_string()).unwrap_or_default(),
+            q.length.map(|l| l.to_string()).unwrap_or_default(),
+            q.source.unwrap_or_default(),
+            preview
+        );
+    }
+    return Ok(());
+}
 
+async fn replay(id: u64, speed: u32) -> Result<()> {
+    let record = find_record(id)?;
+    let log = load_log(id)?;
+    let quote = Quote {
+        text: log.text,
+        source: record.source,
+        id: record.quote_id,
+        length: record.length,
+        index: None,
+    };
+    let mut app = match record.mode {
+        Mode::Time(secs) => App::timed(&quote, secs),
+        mode => App::new(&quote, mode),
+    };
+    app.set_settings(log.settings);
+    app.set_replay(id, log.keystrokes, speed as f64);
     app.start().await?;
     return Ok(());
 }
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Stats) => print_stats(&load_records()?),
+        Some(Command::Collections) => print_collections()?,
+        Some(Command::Search { term, language }) => print_search(&term, language.as_deref())?,
+        Some(Command::Replay { id, speed }) => replay(id, speed).await?,
+        None => type_quote(cli.args).await?,
+    }
+    return Ok(());
+}
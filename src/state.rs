@@ -1,6 +1,39 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
 #[derive(Default)]
 pub struct State {
     pub current: usize,
     pub buffer: String,
     pub buffer_len: usize,
 }
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Key {
+    Press(char),
+    Backspace,
+    DeleteWord,
+}
+
+/// A single key and when it was pressed, relative to the start of the run.
+/// For backspaces `expected` and `correct` describe the character that got
+/// deleted. For word deletions `correct` tells whether what got deleted was
+/// typed correctly.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Keystroke {
+    pub at: Duration,
+    pub key: Key,
+    pub expected: Option<String>,
+    pub correct: bool,
+    /// How long the key was held down, if the terminal reports key releases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held: Option<Duration>,
+}
+
+impl Keystroke {
+    pub fn is_press(&self) -> bool {
+        matches!(self.key, Key::Press(_))
+    }
+}
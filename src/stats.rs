@@ -0,0 +1,239 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    history::{Mode, Record},
+    state::Keystroke,
+};
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const SPARKLINE_LEN: usize = 60;
+const RECENT_COUNT: usize = 10;
+
+fn average(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0), |(s, c), v| (s + v, c + 1));
+    if count == 0 {
+        return 0.0;
+    }
+    return sum / count as f64;
+}
+
+/// Words per minute, with a word being 5 characters long. Runs that took no
+/// time at all, like a single character typed on the first key, count as 0.
+pub fn wpm(chars: f64, duration: Duration) -> f64 {
+    if duration.is_zero() {
+        return 0.0;
+    }
+    return chars / 5.0 * 60.0 / duration.as_secs_f64();
+}
+
+/// Keystrokes in each full second of the run. Whatever got typed after the
+/// last full second is left out: scaled up to a whole second, a few
+/// milliseconds holding the last key would look like a burst of speed. Runs
+/// shorter than a second get a single bucket, scaled to a whole second.
+pub fn keystrokes_per_second<'a>(
+    keystrokes: impl Iterator<Item = &'a Keystroke>,
+    duration: Duration,
+) -> Vec<f64> {
+    if duration.as_secs() == 0 {
+        let count = keystrokes.count() as f64;
+        if duration.is_zero() {
+            return vec![count];
+        }
+        return vec![count / duration.as_secs_f64()];
+    }
+    let mut ris = vec![0.0; duration.as_secs() as usize];
+    for k in keystrokes {
+        if let Some(bucket) = ris.get_mut(k.at.as_secs() as usize) {
+            *bucket += 1.0;
+        }
+    }
+    return ris;
+}
+
+/// Consistency score of the key presses, 100 minus the coefficient of variation (as a
+/// percentage) of the per second typing speed. Clamped to 0.
+pub fn consistency(keystrokes: &[Keystroke], duration: Duration) -> f64 {
+    let per_second = keystrokes_per_second(keystrokes.iter().filter(|k| k.is_press()), duration);
+    let mean = average(per_second.iter().copied());
+    if mean == 0.0 {
+        return 0.0;
+    }
+    let variance = average(per_second.iter().map(|c| (c - mean).powi(2)));
+    return (100.0 - variance.sqrt() / mean * 100.0).max(0.0);
+}
+
+/// Average time in milliseconds keys were held down for. Only known when the
+/// terminal reports key releases.
+pub fn dwell_time(keystrokes: &[Keystroke]) -> Option<f64> {
+    let held: Vec<f64> = keystrokes
+        .iter()
+        .filter_map(|k| k.held)
+        .map(|h| h.as_secs_f64() * 1000.0)
+        .collect();
+    if held.is_empty() {
+        return None;
+    }
+    return Some(average(held.into_iter()));
+}
+
+/// Average time in milliseconds between letting go of a key and pressing the
+/// next one. Negative when keys usually overlap.
+pub fn flight_time(keystrokes: &[Keystroke]) -> Option<f64> {
+    let presses: Vec<&Keystroke> = keystrokes.iter().filter(|k| k.is_press()).collect();
+    let flights: Vec<f64> = presses
+        .windows(2)
+        .filter_map(|w| {
+            let released = w[0].at + w[0].held?;
+            Some((w[1].at.as_secs_f64() - released.as_secs_f64()) * 1000.0)
+        })
+        .collect();
+    if flights.is_empty() {
+        return None;
+    }
+    return Some(average(flights.into_iter()));
+}
+
+/// Shrinks `values` down to at most `width` values, averaging the ones that
+/// end up in the same column.
+pub fn squeeze(values: &[f64], width: usize) -> Vec<f64> {
+    if values.len() <= width || width == 0 {
+        return values.to_vec();
+    }
+    let chunk = values.len().div_ceil(width);
+    values
+        .chunks(chunk)
+        .map(|c| average(c.iter().copied()))
+        .collect()
+}
+
+/// Draws the values as vertical bars going from 0 to `max`, `height` rows
+/// tall. The top row comes first.
+pub fn bar_chart(values: &[f64], max: f64, height: usize) -> Vec<String> {
+    let steps = SPARKS.len();
+    let levels: Vec<usize> = values
+        .iter()
+        .map(|v| {
+            if max <= 0.0 {
+                return 0;
+            }
+            (v / max * (height * steps) as f64).round() as usize
+        })
+        .collect();
+    (0..height)
+        .rev()
+        .map(|row| {
+            levels
+                .iter()
+                .map(|level| match level.saturating_sub(row * steps) {
+                    0 => ' ',
+                    l if l >= steps => SPARKS[steps - 1],
+                    l => SPARKS[l - 1],
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn best(records: &[&Record]) -> f64 {
+    records.iter().map(|r| r.wpm).fold(0.0, f64::max)
+}
+
+/// Draws one bar per value, scaled between the smallest and largest value.
+fn sparkline(values: &[f64]) -> String {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    values
+        .iter()
+        .map(|v| {
+            if (max - min).abs() < f64::EPSILON {
+                return SPARKS[SPARKS.len() / 2];
+            }
+            let i = ((v - min) / (max - min) * (SPARKS.len() - 1) as f64).round() as usize;
+            SPARKS[i]
+        })
+        .collect()
+}
+
+fn print_summary(title: &str, records: &[&Record]) {
+    println!(
+        "{:<12} runs: {:<5} avg WPM: {:<5} best WPM: {:<5} avg accuracy: {}%",
+        title,
+        records.len(),
+        average(records.iter().map(|r| r.wpm)).round(),
+        best(records).round(),
+        average(records.iter().map(|r| r.accuracy)).round(),
+    );
+}
+
+pub fn print_stats(records: &[Record]) {
+    if records.is_empty() {
+        println!("There are no runs in your history yet");
+        return;
+    }
+    let all: Vec<&Record> = records.iter().collect();
+    print_summary("All", &all);
+
+    let recent = &all[all.len().saturating_sub(RECENT_COUNT)..];
+    print_summary(&format!("Last {}", recent.len()), recent);
+
+    println!("\nBy mode");
+    let mut by_mode: HashMap<Mode, Vec<&Record>> = HashMap::new();
+    for r in &all {
+        by_mode.entry(r.mode).or_default().push(r);
+    }
+    let mut modes: Vec<(Mode, Vec<&Record>)> = by_mode.into_iter().collect();
+    modes.sort_by_key(|(mode, _)| *mode);
+    for (mode, rs) in modes {
+        print_summary(&mode.to_string(), &rs);
+    }
+
+    let shown = &all[all.len().saturating_sub(SPARKLINE_LEN)..];
+    let wpms: Vec<f64> = shown.iter().map(|r| r.wpm).collect();
+    println!(
+        "\nWPM over the last {} runs ({} to {})\n{}",
+        shown.len(),
+        wpms.iter().copied().fold(f64::INFINITY, f64::min).round(),
+        wpms.iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max)
+            .round(),
+        sparkline(&wpms)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Key;
+
+    fn presses(times: impl Iterator<Item = f64>) -> Vec<Keystroke> {
+        times
+            .map(|at| Keystroke {
+                at: Duration::from_secs_f64(at),
+                key: Key::Press('a'),
+                expected: None,
+                correct: true,
+                held: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn leaves_the_last_partial_second_out() {
+        // Five keys a second for 10.9 seconds
+        let keystrokes = presses((0..55).map(|i| i as f64 * 0.2));
+        let per_second = keystrokes_per_second(keystrokes.iter(), Duration::from_millis(10_900));
+        assert_eq!(per_second, vec![5.0; 10]);
+        assert_eq!(
+            consistency(&keystrokes, Duration::from_millis(10_900)),
+            100.0
+        );
+    }
+
+    #[test]
+    fn scales_runs_shorter_than_a_second() {
+        let keystrokes = presses([0.0, 0.1, 0.2].into_iter());
+        let per_second = keystrokes_per_second(keystrokes.iter(), Duration::from_millis(500));
+        assert_eq!(per_second, vec![6.0]);
+    }
+}
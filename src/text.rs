@@ -0,0 +1,38 @@
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Composes accented letters, so the same text compares equal no matter if it
+/// was written with precomposed characters or combining marks.
+pub fn normalize(text: &str) -> String {
+    text.nfc().collect()
+}
+
+/// Characters of `text` as the user sees them, combining marks and all.
+pub fn graphemes(text: &str) -> Vec<&str> {
+    text.graphemes(true).collect()
+}
+
+pub fn grapheme_count(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+/// Whether the typed character is the expected one. Accents and other marks
+/// can be ignored, so that "e" matches "é".
+pub fn same_grapheme(typed: &str, expected: &str, ignore_diacritics: bool) -> bool {
+    if !ignore_diacritics {
+        return typed == expected;
+    }
+    let strip = |s: &str| -> String { s.nfd().filter(|c| !is_combining_mark(*c)).collect() };
+    return strip(typed) == strip(expected);
+}
+
+/// Whether `typed` is the start of `expected`, character by character.
+pub fn is_prefix(typed: &str, expected: &str, ignore_diacritics: bool) -> bool {
+    let typed = graphemes(typed);
+    let expected = graphemes(expected);
+    return typed.len() <= expected.len()
+        && typed
+            .iter()
+            .zip(&expected)
+            .all(|(t, e)| same_grapheme(t, e, ignore_diacritics));
+}